@@ -0,0 +1,19 @@
+<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.4" tiledversion="1.4.3" name="tileset" tilewidth="32" tileheight="32" tilecount="180" columns="20">
+ <image source="../tileset.png" width="641" height="288"/>
+ <tile id="0">
+  <properties>
+   <property name="walkable" type="bool" value="false"/>
+  </properties>
+ </tile>
+ <tile id="5">
+  <properties>
+   <property name="cost" type="float" value="1.5"/>
+   <property name="kind" value="water"/>
+  </properties>
+  <animation>
+   <frame tileid="5" duration="200"/>
+   <frame tileid="6" duration="300"/>
+  </animation>
+ </tile>
+</tileset>
@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
 
@@ -10,6 +11,7 @@ impl Plugin for AnimationPlugin {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Animations {
     pub current_animation: String,
     pub animations: HashMap<String, Animation>,
@@ -28,6 +30,7 @@ impl Animations {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Animation {
     current_index: usize,
     sprite_indices: Vec<usize>,
@@ -42,6 +45,40 @@ impl Animation {
     }
 }
 
+/// Sprite indices shown for their own duration each, looping since the game started
+/// Unlike an `Animation` it isn't stepped by a timer, whatever shows it is in sync.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedAnimation {
+    /// Sprite indices with how many seconds they're shown
+    frames: Vec<(usize, f32)>,
+    duration: f32,
+}
+
+impl TimedAnimation {
+    pub fn new(frames: Vec<(usize, f32)>) -> Self {
+        let duration = frames.iter().map(|(_, duration)| duration).sum();
+
+        Self { frames, duration }
+    }
+
+    /// Sprite index shown `elapsed` seconds after the game started
+    pub fn sprite_index(&self, elapsed: f32) -> usize {
+        if self.duration <= 0.0 {
+            return self.frames.first().map_or(0, |(index, _)| *index);
+        }
+
+        let mut time = elapsed % self.duration;
+        for (index, duration) in self.frames.iter() {
+            if time < *duration {
+                return *index;
+            }
+            time -= duration;
+        }
+
+        self.frames.last().map_or(0, |(index, _)| *index)
+    }
+}
+
 fn animation_system(
     time: Res<Time>,
     mut query: Query<(&mut Timer, &mut Animations, &mut TextureAtlasSprite)>,
@@ -62,3 +99,19 @@ fn animation_system(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timed_animation() {
+        let animation = TimedAnimation::new(vec![(4, 0.2), (5, 0.3)]);
+
+        assert_eq!(4, animation.sprite_index(0.0));
+        assert_eq!(4, animation.sprite_index(0.1));
+        assert_eq!(5, animation.sprite_index(0.25));
+        assert_eq!(4, animation.sprite_index(0.55));
+        assert_eq!(5, animation.sprite_index(10.3));
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{Animation, Animations};
+use crate::combat::{Armor, Attack, Health, TargetFilter};
+use crate::math::{Real, Scalar, Vector};
+use crate::projectile::ProjectileKind;
+use crate::unit::{Facing, Unit};
+
+/// Where unit archetypes are defined, one JSON file each, named after the archetype id
+pub const UNITS_DIRECTORY: &str = "assets/units";
+
+const RELOAD_CHECK_SECONDS: f32 = 1.0;
+
+/// Everything a kind of unit has in common, loaded from `UNITS_DIRECTORY`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnitArchetype {
+    pub sprite: SpriteSheetDefinition,
+    /// Sprite sheet indices of each animation, units play "idle", "moving" and "dying"
+    pub animations: HashMap<String, Vec<usize>>,
+    pub max_speed: i32,
+    pub max_force: i32,
+    /// Width and height units collide with
+    pub size: (i32, i32),
+    pub health: i32,
+    pub armor: i32,
+    /// Unarmed units don't have one
+    pub attack: Option<AttackDefinition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpriteSheetDefinition {
+    /// Relative to the assets directory
+    pub texture: String,
+    pub tile_size: (f32, f32),
+    pub columns: usize,
+    pub rows: usize,
+    pub scale: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttackDefinition {
+    pub damage: i32,
+    pub range: i32,
+    pub acquisition_range: i32,
+    /// Ticks between two hits
+    pub cooldown: u32,
+    pub targets: TargetFilter,
+    pub projectile: Option<ProjectileDefinition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectileDefinition {
+    pub speed: i32,
+    pub homing: bool,
+    /// Height of the arc, ballistic projectiles fly over blocked tiles
+    #[serde(default)]
+    pub apex: i32,
+    pub splash_radius: i32,
+}
+
+impl UnitArchetype {
+    /// Facing right
+    pub fn unit(&self, id: &str) -> Unit {
+        Unit {
+            archetype: id.to_string(),
+            selected: false,
+            velocity: Vector::zero(),
+            max_speed: Scalar::from_int(self.max_speed),
+            max_force: Scalar::from_int(self.max_force),
+            size: self.size(),
+            facing: Facing::Right,
+        }
+    }
+
+    pub fn size(&self) -> Vector {
+        Vector::new(Scalar::from_int(self.size.0), Scalar::from_int(self.size.1))
+    }
+
+    /// Starts out idle
+    pub fn animations(&self) -> Animations {
+        let animations = self
+            .animations
+            .iter()
+            .map(|(name, indices)| (name.clone(), Animation::new(indices.clone())))
+            .collect();
+
+        Animations::new("idle".to_string(), animations)
+    }
+
+    pub fn attack(&self) -> Option<Attack> {
+        self.attack.as_ref().map(|attack| Attack {
+            damage: attack.damage,
+            range: Scalar::from_int(attack.range),
+            acquisition_range: Scalar::from_int(attack.acquisition_range),
+            cooldown: attack.cooldown,
+            ready_in: 0,
+            targets: attack.targets,
+            projectile: attack.projectile.as_ref().map(|projectile| ProjectileKind {
+                speed: Scalar::from_int(projectile.speed),
+                homing: projectile.homing,
+                apex: Scalar::from_int(projectile.apex),
+                splash_radius: Scalar::from_int(projectile.splash_radius),
+            }),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ArchetypeError {
+    Io(std::io::Error),
+    Json {
+        path: PathBuf,
+        error: serde_json::Error,
+    },
+}
+
+impl fmt::Display for ArchetypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchetypeError::Io(e) => write!(f, "unit archetype io error: {}", e),
+            ArchetypeError::Json { path, error } => {
+                write!(f, "invalid unit archetype {}: {}", path.display(), error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArchetypeError {}
+
+impl From<std::io::Error> for ArchetypeError {
+    fn from(e: std::io::Error) -> Self {
+        ArchetypeError::Io(e)
+    }
+}
+
+/// Every unit archetype, by id
+/// Loaded from `UNITS_DIRECTORY` by `main` before the app is built, plugins expect the resource.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitArchetypes {
+    archetypes: HashMap<String, UnitArchetype>,
+}
+
+impl UnitArchetypes {
+    /// Loads the `.json` files of `directory`, their name without the extension is the id
+    pub fn load_directory(directory: &str) -> Result<UnitArchetypes, ArchetypeError> {
+        let mut archetypes = HashMap::new();
+
+        for path in definition_files(directory)? {
+            let data = std::fs::read_to_string(&path)?;
+            let archetype: UnitArchetype =
+                serde_json::from_str(&data).map_err(|error| ArchetypeError::Json {
+                    path: path.clone(),
+                    error,
+                })?;
+
+            if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                archetypes.insert(id.to_string(), archetype);
+            }
+        }
+
+        Ok(UnitArchetypes { archetypes })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&UnitArchetype> {
+        self.archetypes.get(id)
+    }
+
+    /// Ids of the archetypes that are new or different in `other`
+    fn changes(&self, other: &UnitArchetypes) -> Vec<String> {
+        let mut changes: Vec<String> = other
+            .archetypes
+            .iter()
+            .filter(|(id, archetype)| self.archetypes.get(*id) != Some(archetype))
+            .map(|(id, _)| id.clone())
+            .collect();
+        changes.sort();
+        changes
+    }
+}
+
+fn definition_files(directory: &str) -> Result<Vec<PathBuf>, ArchetypeError> {
+    let mut paths = vec![];
+
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map_or(false, |extension| extension == "json")
+        {
+            paths.push(path);
+        }
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+/// The archetypes with these ids were reloaded, units of that archetype were updated
+pub struct ArchetypesReloaded(pub Vec<String>);
+
+/// Reloads the unit archetypes when their files change, and updates the units spawned from them
+/// Meant for tweaking units while the game runs, peers in a lockstep game don't reload together.
+pub struct ArchetypeReloadPlugin;
+
+impl Plugin for ArchetypeReloadPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // The unit sprites reload too
+        if !app.resources().contains::<Events<ArchetypesReloaded>>() {
+            app.add_event::<ArchetypesReloaded>();
+        }
+
+        app.insert_resource(ArchetypeWatcher {
+            timer: Timer::from_seconds(RELOAD_CHECK_SECONDS, true),
+            modified: modification_times(UNITS_DIRECTORY),
+        })
+        .add_system(watch_system.system())
+        .add_system(update_units_system.system());
+    }
+}
+
+struct ArchetypeWatcher {
+    timer: Timer,
+    modified: HashMap<PathBuf, SystemTime>,
+}
+
+fn modification_times(directory: &str) -> HashMap<PathBuf, SystemTime> {
+    definition_files(directory)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((path, modified))
+        })
+        .collect()
+}
+
+fn watch_system(
+    time: Res<Time>,
+    mut watcher: ResMut<ArchetypeWatcher>,
+    mut archetypes: ResMut<UnitArchetypes>,
+    mut reloaded: ResMut<Events<ArchetypesReloaded>>,
+) {
+    watcher.timer.tick(time.delta_seconds());
+    if !watcher.timer.finished() {
+        return;
+    }
+
+    let modified = modification_times(UNITS_DIRECTORY);
+    if modified == watcher.modified {
+        return;
+    }
+    watcher.modified = modified;
+
+    match UnitArchetypes::load_directory(UNITS_DIRECTORY) {
+        Ok(new_archetypes) => {
+            let changes = archetypes.changes(&new_archetypes);
+            *archetypes = new_archetypes;
+
+            if !changes.is_empty() {
+                info!("Reloaded unit archetypes {:?}", changes);
+                reloaded.send(ArchetypesReloaded(changes));
+            }
+        }
+        // Keep playing with the previous ones until the file is fixed
+        Err(e) => error!("Failed to reload unit archetypes: {}", e),
+    }
+}
+
+fn update_units_system(
+    mut reader: EventReader<ArchetypesReloaded>,
+    archetypes: Res<UnitArchetypes>,
+    mut query: Query<(
+        &mut Unit,
+        &mut Health,
+        Option<&mut Armor>,
+        Option<&mut Attack>,
+    )>,
+) {
+    for ArchetypesReloaded(ids) in reader.iter() {
+        for (mut unit, mut health, armor, attack) in query.iter_mut() {
+            if !ids.contains(&unit.archetype) {
+                continue;
+            }
+
+            let archetype = match archetypes.get(&unit.archetype) {
+                Some(archetype) => archetype,
+                None => continue,
+            };
+
+            unit.max_speed = Scalar::from_int(archetype.max_speed);
+            unit.max_force = Scalar::from_int(archetype.max_force);
+            unit.size = archetype.size();
+
+            health.max = archetype.health;
+            health.current = health.current.min(health.max);
+
+            if let Some(mut armor) = armor {
+                armor.0 = archetype.armor;
+            }
+
+            if let (Some(mut attack), Some(new_attack)) = (attack, archetype.attack()) {
+                // Reloading doesn't reset the cooldown
+                *attack = Attack {
+                    ready_in: attack.ready_in.min(new_attack.cooldown),
+                    ..new_attack
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_directory() {
+        let archetypes =
+            UnitArchetypes::load_directory(UNITS_DIRECTORY).expect("Failed to load archetypes");
+
+        let dino = archetypes.get("dino").expect("Missing dino");
+        assert_eq!(100, dino.health);
+        assert_eq!(
+            vec![1, 2, 3, 4],
+            dino.animations.get("idle").cloned().unwrap_or_default()
+        );
+
+        let unit = dino.unit("dino");
+        assert_eq!("dino", unit.archetype);
+        assert_eq!(Scalar::from_int(100), unit.max_speed);
+        assert!(dino
+            .attack()
+            .map_or(false, |attack| attack.projectile.is_none()));
+
+        let spitter = archetypes.get("spitter").expect("Missing spitter");
+        assert!(spitter
+            .attack()
+            .map_or(false, |attack| attack.projectile.is_some()));
+    }
+
+    #[test]
+    fn test_changes() {
+        let archetypes =
+            UnitArchetypes::load_directory(UNITS_DIRECTORY).expect("Failed to load archetypes");
+        let mut changed = archetypes.clone();
+        changed
+            .archetypes
+            .get_mut("dino")
+            .expect("Missing dino")
+            .max_speed = 150;
+
+        assert!(archetypes.changes(&archetypes).is_empty());
+        assert_eq!(vec!["dino".to_string()], archetypes.changes(&changed));
+    }
+}
@@ -0,0 +1,37 @@
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_startup_system(setup.system())
+            .add_system(camera_movement.system());
+    }
+}
+
+fn setup(commands: &mut Commands) {
+    commands
+        // cameras
+        .spawn(OrthographicCameraBundle::new_2d());
+}
+
+fn camera_movement(keys: Res<Input<KeyCode>>, mut query: Query<(&Camera, &mut Transform)>) {
+    if let Some((_camera, mut transform)) = query.iter_mut().next() {
+        if keys.pressed(KeyCode::Right) {
+            transform.translation.x += 6.0
+        }
+
+        if keys.pressed(KeyCode::Left) {
+            transform.translation.x -= 6.0
+        }
+
+        if keys.pressed(KeyCode::Up) {
+            transform.translation.y += 6.0
+        }
+
+        if keys.pressed(KeyCode::Down) {
+            transform.translation.y -= 6.0
+        }
+    }
+}
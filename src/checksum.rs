@@ -0,0 +1,41 @@
+use crate::math::{Real, Scalar, Vector};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// FNV-1a hash of the simulation state
+/// `DefaultHasher` is not guaranteed to be stable across Rust versions, this one is.
+#[derive(Debug, Clone, Copy)]
+pub struct Checksum(u64);
+
+impl Checksum {
+    pub fn new() -> Self {
+        Checksum(FNV_OFFSET_BASIS)
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        for byte in value.to_le_bytes().iter() {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn write_scalar(&mut self, value: Scalar) {
+        self.write_u64(value.checksum_bits());
+    }
+
+    pub fn write_vector(&mut self, value: Vector) {
+        self.write_scalar(value.x);
+        self.write_scalar(value.y);
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
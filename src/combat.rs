@@ -0,0 +1,475 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::math::{Real, Scalar, Vector};
+use crate::orders::order_move;
+use crate::path_finding::grid::Grid;
+use crate::player::{Owner, PlayerId, Players, Relation};
+use crate::projectile::{self, Projectile, ProjectileKind};
+use crate::simulation::{SIMULATION_STAGE, TICKS_PER_SECOND};
+use crate::spatial::SpatialGrid;
+use crate::unit::{AttackMoveOrder, MoveOrder, Position, Unit};
+
+/// Units closer than this to a click are the ones clicked on
+pub const CLICK_RADIUS: i32 = 16;
+/// How long dead units stay around, for their death animation
+pub const DEATH_TICKS: u32 = TICKS_PER_SECOND as u32;
+
+// About the longest attack range, so range queries look at a few cells at most
+const SPATIAL_CELL_SIZE: i32 = 64;
+// Attack-moves are over once this close to their target
+const ARRIVAL_DISTANCE: i32 = 16;
+
+/// Units attack the targets they were ordered to, idle ones fight back
+/// whatever comes in range. Units with no health left die and despawn.
+pub struct CombatPlugin;
+
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(SIMULATION_STAGE, attack_move_system.system())
+            .add_system_to_stage(SIMULATION_STAGE, attack_order_system.system())
+            .add_system_to_stage(SIMULATION_STAGE, attack_system.system())
+            .add_system_to_stage(SIMULATION_STAGE, death_system.system())
+            .add_system_to_stage(SIMULATION_STAGE, corpse_system.system());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+    pub current: i32,
+    pub max: i32,
+}
+
+impl Health {
+    pub fn new(max: i32) -> Self {
+        Self { current: max, max }
+    }
+}
+
+/// Taken off the damage of every hit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Armor(pub i32);
+
+/// Which units an attack can target
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TargetFilter {
+    Enemies,
+    /// Own and allied units, for attacks with negative damage that heal
+    Friends,
+}
+
+impl TargetFilter {
+    pub fn accepts(self, relation: Relation) -> bool {
+        match self {
+            TargetFilter::Enemies => relation == Relation::Enemy,
+            TargetFilter::Friends => relation != Relation::Enemy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attack {
+    pub damage: i32,
+    pub range: Scalar,
+    /// Idle and attack-moving units go after the enemies this close
+    pub acquisition_range: Scalar,
+    /// Ticks between two hits
+    pub cooldown: u32,
+    /// Ticks until the next hit
+    pub ready_in: u32,
+    pub targets: TargetFilter,
+    /// Fired at the target, which is hit right away otherwise
+    pub projectile: Option<ProjectileKind>,
+}
+
+/// Units with an `Attack` chase the `target` they were ordered to attack until it's in range,
+/// then attack it until it dies
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AttackOrder {
+    pub target: Option<Entity>,
+}
+
+/// Dead unit, despawns once its death animation played
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dying {
+    pub ticks_left: u32,
+}
+
+/// Health left after a hit of `damage`, hits always do some damage, heals never overheal
+pub fn apply_hit(health: &Health, armor: Option<&Armor>, damage: i32) -> i32 {
+    if damage < 0 {
+        return (health.current - damage).min(health.max);
+    }
+
+    let armor = armor.map_or(0, |armor| armor.0);
+    health.current - (damage - armor).max(1)
+}
+
+/// The unit closest to `point`, within `CLICK_RADIUS` of it, accepted by `filter`
+pub fn unit_at<'a>(
+    point: Vector,
+    units: impl Iterator<Item = (Entity, &'a Owner, &'a Position)>,
+    filter: impl Fn(&Owner) -> bool,
+) -> Option<Entity> {
+    let radius = Scalar::from_int(CLICK_RADIUS);
+    let mut closest: Option<(Scalar, Entity)> = None;
+
+    for (entity, owner, position) in units {
+        let distance = (position.0 - point).length();
+
+        if distance <= radius
+            && filter(owner)
+            && closest.map_or(true, |(closest_distance, _)| distance < closest_distance)
+        {
+            closest = Some((distance, entity));
+        }
+    }
+
+    closest.map(|(_, entity)| entity)
+}
+
+/// Attack-moving units go after the closest enemy in acquisition range,
+/// and path to their target again once it's dead
+fn attack_move_system(
+    grid: Res<Grid>,
+    players: Res<Players>,
+    mut movers: Query<
+        (
+            Entity,
+            &Owner,
+            &Position,
+            &Attack,
+            &mut AttackOrder,
+            &mut AttackMoveOrder,
+            &mut Unit,
+            &mut MoveOrder,
+        ),
+        Without<Dying>,
+    >,
+    targets: Query<(Entity, &Owner, &Position), (With<Health>, Without<Dying>)>,
+) {
+    let mut spatial_grid = SpatialGrid::new(Scalar::from_int(SPATIAL_CELL_SIZE));
+    let mut owners: HashMap<Entity, PlayerId> = HashMap::new();
+
+    for (entity, owner, position) in targets.iter() {
+        spatial_grid.insert(entity, position.0);
+        owners.insert(entity, owner.0);
+    }
+
+    for (entity, owner, position, attack, mut order, mut attack_move, mut unit, mut move_order) in
+        movers.iter_mut()
+    {
+        let target = match attack_move.target {
+            Some(target) => target,
+            None => continue,
+        };
+
+        // Busy fighting
+        if order.target.is_some() {
+            continue;
+        }
+
+        let enemy = closest(
+            position.0,
+            spatial_grid
+                .in_range(position.0, attack.acquisition_range)
+                .into_iter()
+                .filter(|(enemy, _)| {
+                    *enemy != entity
+                        && owners.get(enemy).map_or(false, |enemy_owner| {
+                            attack
+                                .targets
+                                .accepts(players.relation(owner.0, *enemy_owner))
+                        })
+                }),
+        );
+
+        if let Some(enemy) = enemy {
+            order.target = Some(enemy);
+        } else if (target - position.0).length() <= Scalar::from_int(ARRIVAL_DISTANCE) {
+            attack_move.target = None;
+        } else if move_order.path.is_empty() {
+            // The fight is over
+            order_move(&grid, position, target, &mut unit, &mut move_order);
+        }
+    }
+}
+
+/// The entity closest to `position` among `candidates`, the first one when tied
+fn closest(position: Vector, candidates: impl Iterator<Item = (Entity, Vector)>) -> Option<Entity> {
+    candidates
+        .min_by(|(_, a), (_, b)| {
+            let a = (*a - position).length_squared();
+            let b = (*b - position).length_squared();
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        })
+        .map(|(entity, _)| entity)
+}
+
+fn attack_order_system(
+    grid: Res<Grid>,
+    mut attackers: Query<
+        (
+            &Position,
+            &Attack,
+            &mut AttackOrder,
+            &mut Unit,
+            &mut MoveOrder,
+        ),
+        Without<Dying>,
+    >,
+    targets: Query<&Position, (With<Health>, Without<Dying>)>,
+) {
+    for (position, attack, mut order, mut unit, mut move_order) in attackers.iter_mut() {
+        let target = match order.target.map(|target| targets.get(target)) {
+            Some(Ok(target)) => target.0,
+            Some(Err(_)) => {
+                // Dead already
+                order.target = None;
+                move_order.path.clear();
+                continue;
+            }
+            None => continue,
+        };
+
+        if (target - position.0).length() <= attack.range {
+            move_order.path.clear();
+            continue;
+        }
+
+        // Path again once the target moved away from where the path leads
+        let stale = move_order
+            .path
+            .last()
+            .map_or(true, |end| (*end - target).length() > attack.range);
+
+        if stale {
+            order_move(&grid, position, target, &mut unit, &mut move_order);
+        }
+    }
+}
+
+fn attack_system(
+    commands: &mut Commands,
+    players: Res<Players>,
+    mut attackers: Query<
+        (
+            Entity,
+            &Owner,
+            &Position,
+            &MoveOrder,
+            &mut Attack,
+            &AttackOrder,
+        ),
+        Without<Dying>,
+    >,
+    mut q: QuerySet<(
+        Query<(Entity, &Owner, &Position), (With<Health>, Without<Dying>)>,
+        Query<(&mut Health, Option<&Armor>)>,
+    )>,
+) {
+    let mut grid = SpatialGrid::new(Scalar::from_int(SPATIAL_CELL_SIZE));
+    let mut owners: HashMap<Entity, PlayerId> = HashMap::new();
+    let mut positions: HashMap<Entity, Vector> = HashMap::new();
+
+    for (entity, owner, position) in q.q0().iter() {
+        grid.insert(entity, position.0);
+        owners.insert(entity, owner.0);
+        positions.insert(entity, position.0);
+    }
+
+    let mut hits: Vec<(Entity, i32)> = vec![];
+
+    for (entity, owner, position, move_order, mut attack, order) in attackers.iter_mut() {
+        if attack.ready_in > 0 {
+            attack.ready_in -= 1;
+            continue;
+        }
+
+        let in_range = grid.in_range(position.0, attack.range);
+        let filter = attack.targets;
+        let valid = |target: &Entity| {
+            *target != entity
+                && owners.get(target).map_or(false, |target_owner| {
+                    filter.accepts(players.relation(owner.0, *target_owner))
+                })
+        };
+
+        let target = match order.target {
+            Some(ordered) => in_range
+                .iter()
+                .map(|(target, _)| *target)
+                .find(|target| *target == ordered && valid(target)),
+            // Moving units don't stop to fight
+            None if move_order.path.is_empty() => closest(
+                position.0,
+                in_range.iter().cloned().filter(|(target, _)| valid(target)),
+            ),
+            None => None,
+        };
+
+        if let Some(target) = target {
+            match &attack.projectile {
+                Some(kind) => projectile::spawn_projectile(
+                    commands,
+                    Projectile {
+                        owner: owner.0,
+                        kind: kind.clone(),
+                        damage: attack.damage,
+                        targets: attack.targets,
+                        target: if kind.homing { Some(target) } else { None },
+                        origin: position.0,
+                        destination: positions[&target],
+                    },
+                    position.0,
+                ),
+                None => hits.push((target, attack.damage)),
+            }
+            attack.ready_in = attack.cooldown;
+        }
+    }
+
+    for (target, damage) in hits {
+        if let Ok((mut health, armor)) = q.q1_mut().get_mut(target) {
+            health.current = apply_hit(&health, armor, damage);
+        }
+    }
+}
+
+fn death_system(
+    commands: &mut Commands,
+    mut query: Query<(Entity, &Health, &mut Unit, &mut MoveOrder), Without<Dying>>,
+) {
+    for (entity, health, mut unit, mut move_order) in query.iter_mut() {
+        if health.current > 0 {
+            continue;
+        }
+
+        unit.selected = false;
+        unit.velocity = Vector::zero();
+        move_order.path.clear();
+
+        commands.insert_one(
+            entity,
+            Dying {
+                ticks_left: DEATH_TICKS,
+            },
+        );
+    }
+}
+
+fn corpse_system(commands: &mut Commands, mut query: Query<(Entity, &mut Dying)>) {
+    for (entity, mut dying) in query.iter_mut() {
+        if dying.ticks_left == 0 {
+            commands.despawn(entity);
+        } else {
+            dying.ticks_left -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::PlayerCommand;
+    use crate::test_harness::TestApp;
+
+    fn selected_health(app: &TestApp) -> Vec<i32> {
+        app.app
+            .world
+            .query::<(&Health, &Unit)>()
+            .filter(|(_, unit)| unit.selected)
+            .map(|(health, _)| health.current)
+            .collect()
+    }
+
+    #[test]
+    fn test_apply_hit() {
+        let health = Health {
+            current: 50,
+            max: 100,
+        };
+
+        assert_eq!(42, apply_hit(&health, Some(&Armor(2)), 10));
+        assert_eq!(49, apply_hit(&health, Some(&Armor(20)), 10));
+        assert_eq!(40, apply_hit(&health, None, 10));
+        assert_eq!(100, apply_hit(&health, None, -80));
+    }
+
+    #[test]
+    fn test_attack_until_target_dies() {
+        let mut app = TestApp::new();
+
+        // The enemy dino at (23, 42) doesn't have much health left
+        let target = app
+            .app
+            .world
+            .query::<(Entity, &Position)>()
+            .find(|(_, position)| position.0.to_vec2() == Vec2::new(23.0, 42.0))
+            .map(|(entity, _)| entity)
+            .expect("Missing target");
+        app.app.world.get_mut::<Health>(target).unwrap().current = 20;
+
+        app.send_command(PlayerCommand::Select {
+            min: Vector::from(Vec2::new(-20.0, -20.0)),
+            max: Vector::from(Vec2::new(20.0, 20.0)),
+        });
+        app.send_command(PlayerCommand::Attack {
+            target: Vector::from(Vec2::new(25.0, 40.0)),
+        });
+        app.step_seconds(6.0);
+
+        assert_eq!(3, app.unit_positions().len());
+        assert!(!app.unit_positions().contains(&Vec2::new(23.0, 42.0)));
+
+        // It fought back before dying
+        let health = selected_health(&app);
+        assert_eq!(1, health.len());
+        assert!(health[0] > 0 && health[0] < 100);
+    }
+
+    #[test]
+    fn test_attack_move_fights_on_the_way() {
+        let mut app = TestApp::new();
+
+        // The enemy dino at (23, 42) dies from a single hit
+        let enemy = app
+            .app
+            .world
+            .query::<(Entity, &Position)>()
+            .find(|(_, position)| position.0.to_vec2() == Vec2::new(23.0, 42.0))
+            .map(|(entity, _)| entity)
+            .expect("Missing enemy");
+        app.app.world.get_mut::<Health>(enemy).unwrap().current = 5;
+
+        app.send_command(PlayerCommand::Select {
+            min: Vector::from(Vec2::new(-20.0, -20.0)),
+            max: Vector::from(Vec2::new(20.0, 20.0)),
+        });
+        let target = Vec2::new(-200.0, 0.0);
+        app.send_command(PlayerCommand::AttackMove {
+            target: Vector::from(target),
+        });
+        app.step_seconds(15.0);
+
+        assert_eq!(3, app.unit_positions().len());
+        assert!(!app.unit_positions().contains(&Vec2::new(23.0, 42.0)));
+
+        // Then carried on to the target
+        let position = app
+            .app
+            .world
+            .query::<(&Position, &Unit)>()
+            .find(|(_, unit)| unit.selected)
+            .map(|(position, _)| position.0.to_vec2())
+            .expect("Missing attacker");
+        assert!(
+            (position - target).length() < 16.0,
+            "Unit stopped at {}",
+            position
+        );
+    }
+}
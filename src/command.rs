@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::math::Vector;
+use crate::player::PlayerId;
+
+/// Something a player asks the simulation to do
+/// Input systems never touch the units directly, they send these as events instead
+/// so AI, scripts, replays, network peers and tests can issue the exact same orders.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PlayerCommand {
+    /// Selects the units inside the box spanning `min` to `max`, deselects the others
+    Select { min: Vector, max: Vector },
+    /// Moves the selected units to `target`
+    Move { target: Vector },
+    /// Selected units drop their orders and stop where they are
+    Stop,
+    /// Selected units attack the enemy unit at `target`
+    Attack { target: Vector },
+    /// Selected units move to `target`, fighting the enemies they meet on the way
+    AttackMove { target: Vector },
+}
+
+/// Who decides on which tick the local player's commands run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandScheduling {
+    /// The next tick
+    NextTick,
+    /// The networking layer, once every peer knows about them
+    Network,
+}
+
+/// A command, who issued it and the tick it ran on, sent as an event once the simulation applied it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TickCommand {
+    pub tick: u64,
+    pub player: PlayerId,
+    pub command: PlayerCommand,
+}
+
+/// Commands waiting for their tick, along with the player who issued them
+#[derive(Debug, Default)]
+pub struct CommandQueue {
+    commands: BTreeMap<u64, Vec<(PlayerId, PlayerCommand)>>,
+}
+
+impl CommandQueue {
+    pub fn schedule(&mut self, tick: u64, player: PlayerId, command: PlayerCommand) {
+        self.commands
+            .entry(tick)
+            .or_default()
+            .push((player, command));
+    }
+
+    /// Removes the commands due at `tick`, late ones included, in the order they were scheduled
+    pub fn take_due(&mut self, tick: u64) -> Vec<(PlayerId, PlayerCommand)> {
+        let later = self.commands.split_off(&(tick + 1));
+        let due = std::mem::replace(&mut self.commands, later);
+        due.into_iter().flat_map(|(_, commands)| commands).collect()
+    }
+
+    /// Every command still waiting for its tick
+    pub fn pending(&self) -> Vec<TickCommand> {
+        self.commands
+            .iter()
+            .flat_map(|(tick, commands)| {
+                commands.iter().map(move |(player, command)| TickCommand {
+                    tick: *tick,
+                    player: *player,
+                    command: command.clone(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_due_commands() {
+        let mut queue = CommandQueue::default();
+        let target = Vector::zero();
+
+        let player = PlayerId(0);
+
+        queue.schedule(3, player, PlayerCommand::Move { target });
+        queue.schedule(1, player, PlayerCommand::Move { target });
+        queue.schedule(2, player, PlayerCommand::Move { target });
+
+        assert_eq!(2, queue.take_due(2).len());
+        assert_eq!(0, queue.take_due(2).len());
+        assert_eq!(1, queue.take_due(3).len());
+    }
+}
@@ -0,0 +1,220 @@
+use bevy::app::AppExit;
+use bevy::prelude::*;
+
+use crate::archetype::UnitArchetypes;
+use crate::combat::Health;
+use crate::command::{CommandQueue, PlayerCommand};
+use crate::lockstep::{self, Lockstep, LockstepPlugin, LoopbackTransport};
+use crate::path_finding::grid::Grid;
+use crate::player::Owner;
+use crate::plugins::SimulationPlugins;
+use crate::projectile::Projectile;
+use crate::script::Script;
+use crate::simulation::{MapPath, Tick, TickMode, SIMULATION_STAGE};
+use crate::tiled;
+use crate::unit::{MoveOrder, Position, Unit};
+
+/// Options for `--headless` runs:
+/// `--headless [--map <path>] [--script <path>] [--ticks <n>] [--loopback]`
+/// `--loopback` runs two simulations in lockstep, the first one playing the script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadlessOptions {
+    pub map: String,
+    pub script: Option<String>,
+    pub ticks: u64,
+    pub loopback: bool,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            map: "assets/basic_map.tmj".to_string(),
+            script: None,
+            ticks: 600,
+            loopback: false,
+        }
+    }
+}
+
+impl HeadlessOptions {
+    pub fn from_args(args: &[String]) -> Result<HeadlessOptions, String> {
+        let mut options = HeadlessOptions::default();
+        let mut args = args.iter().skip(1);
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+
+            match arg.as_str() {
+                "--headless" => {}
+                "--loopback" => options.loopback = true,
+                "--map" => options.map = value()?,
+                "--script" => options.script = Some(value()?),
+                "--ticks" => {
+                    options.ticks = value()?
+                        .parse()
+                        .map_err(|_| "--ticks expects a number".to_string())?
+                }
+                _ => return Err(format!("Unknown argument {}", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// How many ticks to run before printing the state and exiting
+struct TickLimit(u64);
+
+/// Runs the simulation without a window or renderer, as fast as possible
+pub fn run(options: HeadlessOptions, archetypes: UnitArchetypes, map: tiled::Map, grid: Grid) {
+    if options.loopback {
+        run_loopback(options, archetypes, map, grid);
+        return;
+    }
+
+    build_app(&options, archetypes, map, grid)
+        .insert_resource(TickLimit(options.ticks))
+        .add_startup_system(schedule_script_system.system())
+        .add_system_to_stage(SIMULATION_STAGE, exit_system.system())
+        .run();
+}
+
+fn build_app(
+    options: &HeadlessOptions,
+    archetypes: UnitArchetypes,
+    map: tiled::Map,
+    path_finding_grid: Grid,
+) -> AppBuilder {
+    let script = match &options.script {
+        Some(path) => Script::from_file(path).expect("Couldnt load script"),
+        None => Script::default(),
+    };
+
+    let mut app = App::build();
+    app.insert_resource(TickMode::PerUpdate)
+        .insert_resource(script)
+        .insert_resource(MapPath(options.map.clone()))
+        .insert_resource(path_finding_grid)
+        .insert_resource(map)
+        .insert_resource(archetypes)
+        .add_plugins(MinimalPlugins)
+        .add_plugins(SimulationPlugins);
+    app
+}
+
+/// Two simulations exchanging commands through a `LoopbackTransport`,
+/// the state of both is printed at the end along with whether they match.
+fn run_loopback(options: HeadlessOptions, archetypes: UnitArchetypes, map: tiled::Map, grid: Grid) {
+    let mut apps: Vec<App> = LoopbackTransport::network(2)
+        .into_iter()
+        .enumerate()
+        .map(|(player, transport)| {
+            let mut app = build_app(&options, archetypes.clone(), map.clone(), grid.clone());
+            app.insert_resource(Lockstep::new(player as u8, 2, Box::new(transport)))
+                .add_plugin(LockstepPlugin);
+
+            if player == 0 {
+                app.add_system(script_input_system.system());
+            }
+
+            app.app
+        })
+        .collect();
+
+    let tick = |app: &App| app.resources.get::<Tick>().expect("Missing Tick").0;
+
+    while apps.iter().any(|app| tick(app) < options.ticks) {
+        for app in apps.iter_mut() {
+            if tick(app) < options.ticks {
+                app.update();
+            }
+        }
+    }
+
+    let mut checksums = vec![];
+    for (player, app) in apps.iter().enumerate() {
+        println!("player {}", player);
+        print_units(
+            tick(app),
+            app.world.query::<(Entity, &Position, &Unit, &MoveOrder)>(),
+        );
+
+        checksums.push(lockstep::state_checksum(
+            app.world
+                .query::<(&Owner, &Unit, &Position, &MoveOrder, &Health)>(),
+            app.world.query::<(&Projectile, &Position)>(),
+        ));
+    }
+
+    if checksums.windows(2).all(|pair| pair[0] == pair[1]) {
+        println!("in sync, checksum {}", checksums[0]);
+    } else {
+        println!("desync, checksums {:?}", checksums);
+    }
+}
+
+/// Issues the script commands like a player would, they run after the lockstep input delay
+fn script_input_system(
+    tick: Res<Tick>,
+    script: Res<Script>,
+    mut next_command: Local<usize>,
+    mut player_commands: ResMut<Events<PlayerCommand>>,
+) {
+    while let Some(tick_command) = script.commands.get(*next_command) {
+        if tick_command.tick > tick.0 + 1 {
+            break;
+        }
+
+        player_commands.send(tick_command.command.clone());
+        *next_command += 1;
+    }
+}
+
+fn schedule_script_system(script: Res<Script>, mut queue: ResMut<CommandQueue>) {
+    for tick_command in script.commands.iter() {
+        queue.schedule(
+            tick_command.tick,
+            tick_command.player,
+            tick_command.command.clone(),
+        );
+    }
+}
+
+fn exit_system(
+    tick: Res<Tick>,
+    limit: Res<TickLimit>,
+    mut app_exit_events: ResMut<Events<AppExit>>,
+    query: Query<(Entity, &Position, &Unit, &MoveOrder)>,
+) {
+    if tick.0 < limit.0 {
+        return;
+    }
+
+    print_units(tick.0, query.iter());
+
+    app_exit_events.send(AppExit);
+}
+
+fn print_units<'a>(
+    tick: u64,
+    units: impl Iterator<Item = (Entity, &'a Position, &'a Unit, &'a MoveOrder)>,
+) {
+    let mut units: Vec<_> = units.collect();
+    units.sort_by_key(|(entity, ..)| entity.id());
+
+    println!("tick {}", tick);
+    for (entity, position, unit, move_order) in units {
+        println!(
+            "unit {} position {} velocity {} selected {} waypoints {}",
+            entity.id(),
+            position.0,
+            unit.velocity,
+            unit.selected,
+            move_order.path.len()
+        );
+    }
+}
@@ -0,0 +1,422 @@
+mod transport;
+
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::checksum::Checksum;
+use crate::combat::Health;
+use crate::command::{CommandQueue, CommandScheduling, PlayerCommand};
+use crate::player::{LocalPlayer, Owner, PlayerId};
+use crate::projectile::Projectile;
+use crate::simulation::{SimulationClock, Tick, SIMULATION_STAGE};
+use crate::unit::{MoveOrder, Position, Unit};
+
+pub use self::transport::{LoopbackTransport, Transport, UdpTransport};
+
+/// Commands are exchanged per turn instead of per tick
+pub const TURN_TICKS: u64 = 6;
+/// A command issued during turn `n` runs on turn `n + INPUT_DELAY_TURNS`,
+/// giving it time to reach every peer.
+pub const INPUT_DELAY_TURNS: u64 = 2;
+/// Checksums are sent once, the turns a peer's checksum got lost for are given up on
+/// once they're this old
+const CHECKSUM_TIMEOUT_TURNS: u64 = INPUT_DELAY_TURNS * 4;
+
+/// Deterministic lockstep: every peer runs the full simulation and they only exchange
+/// commands. A turn runs once the commands of every player for it arrived, so all
+/// simulations see the same commands on the same ticks.
+/// Expects a `Lockstep` resource, which owns the connection to the peers.
+pub struct LockstepPlugin;
+
+impl Plugin for LockstepPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let player = app
+            .resources()
+            .get::<Lockstep>()
+            .expect("Missing Lockstep")
+            .player;
+
+        #[cfg(not(feature = "fixed-point"))]
+        warn!(
+            "Lockstep without the fixed-point feature, \
+             peers doing float math differently will desync"
+        );
+
+        app.insert_resource(CommandScheduling::Network)
+            .insert_resource(LocalPlayer(PlayerId(player)))
+            .add_event::<Desync>()
+            .add_system(lockstep_system.system())
+            .add_system_to_stage(SIMULATION_STAGE, turn_checksum_system.system());
+    }
+}
+
+/// Sent when a peer ended a turn with a different state than ours
+#[derive(Debug, Clone, PartialEq)]
+pub struct Desync {
+    pub turn: u64,
+    pub player: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum Message {
+    /// A player's commands for a turn, along with the first turn it's still missing
+    /// commands for, so peers know what to send again
+    Turn {
+        player: u8,
+        turn: u64,
+        commands: Vec<PlayerCommand>,
+        missing_turn: u64,
+    },
+    /// State checksum at the end of a turn
+    Checksum {
+        player: u8,
+        turn: u64,
+        checksum: u64,
+    },
+}
+
+pub struct Lockstep {
+    pub player: u8,
+    players: u8,
+    transport: Box<dyn Transport>,
+    /// Commands issued since the last batch was sent
+    local_commands: Vec<PlayerCommand>,
+    /// Commands per turn, per player
+    turns: BTreeMap<u64, BTreeMap<u8, Vec<PlayerCommand>>>,
+    /// Local commands per turn, kept until every peer received them
+    sent_turns: BTreeMap<u64, Vec<PlayerCommand>>,
+    /// First turn the local commands were not sent for yet
+    next_send_turn: u64,
+    /// First turn not handed to the simulation yet
+    next_schedule_turn: u64,
+    /// First turn each peer is missing commands for
+    peers_missing_turn: HashMap<u8, u64>,
+    /// Checksums of the turns some peers didn't send theirs for yet
+    local_checksums: BTreeMap<u64, u64>,
+    peer_checksums: BTreeMap<u64, Vec<(u8, u64)>>,
+    pub last_desync: Option<Desync>,
+}
+
+impl Lockstep {
+    pub fn new(player: u8, players: u8, transport: Box<dyn Transport>) -> Self {
+        Self {
+            player,
+            players,
+            transport,
+            local_commands: vec![],
+            turns: BTreeMap::new(),
+            sent_turns: BTreeMap::new(),
+            // Nobody can issue commands for the first turns
+            next_send_turn: INPUT_DELAY_TURNS,
+            next_schedule_turn: 0,
+            peers_missing_turn: HashMap::new(),
+            local_checksums: BTreeMap::new(),
+            peer_checksums: BTreeMap::new(),
+            last_desync: None,
+        }
+    }
+
+    /// Ticks of `turn` are `first_tick(turn)..first_tick(turn + 1)`
+    pub fn first_tick(turn: u64) -> u64 {
+        turn * TURN_TICKS + 1
+    }
+
+    fn send(&mut self, message: &Message) {
+        let packet = serde_json::to_vec(message).expect("Failed to serialize lockstep message");
+        self.transport.broadcast(&packet);
+    }
+
+    fn receive(&mut self) {
+        for packet in self.transport.receive() {
+            let message = match serde_json::from_slice::<Message>(&packet) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Ignoring invalid lockstep message: {}", e);
+                    continue;
+                }
+            };
+
+            match message {
+                Message::Turn {
+                    player,
+                    turn,
+                    commands,
+                    missing_turn,
+                } => {
+                    if turn >= self.next_schedule_turn {
+                        self.turns.entry(turn).or_default().insert(player, commands);
+                    }
+
+                    let peer_missing_turn = self.peers_missing_turn.entry(player).or_insert(0);
+                    *peer_missing_turn = (*peer_missing_turn).max(missing_turn);
+                }
+                Message::Checksum {
+                    player,
+                    turn,
+                    checksum,
+                } => {
+                    let checksums = self.peer_checksums.entry(turn).or_default();
+                    if checksums.iter().all(|(peer, _)| *peer != player) {
+                        checksums.push((player, checksum));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the local commands for every turn that can't receive new ones anymore
+    fn send_turns(&mut self, tick: u64) {
+        let current_turn = tick / TURN_TICKS;
+
+        while self.next_send_turn <= current_turn + INPUT_DELAY_TURNS {
+            let turn = self.next_send_turn;
+            let commands = std::mem::take(&mut self.local_commands);
+
+            self.turns
+                .entry(turn)
+                .or_default()
+                .insert(self.player, commands.clone());
+            self.sent_turns.insert(turn, commands);
+            self.next_send_turn += 1;
+        }
+
+        // UDP might lose packets, send everything a peer could still be missing
+        let oldest_missing_turn = (0..self.players)
+            .filter(|player| *player != self.player)
+            .map(|player| *self.peers_missing_turn.get(&player).unwrap_or(&0))
+            .min()
+            .unwrap_or(self.next_send_turn);
+
+        self.sent_turns = self.sent_turns.split_off(&oldest_missing_turn);
+
+        let batches: Vec<(u64, Vec<PlayerCommand>)> = self
+            .sent_turns
+            .iter()
+            .map(|(turn, commands)| (*turn, commands.clone()))
+            .collect();
+
+        for (turn, commands) in batches {
+            let message = Message::Turn {
+                player: self.player,
+                turn,
+                commands,
+                missing_turn: self.next_schedule_turn,
+            };
+            self.send(&message);
+        }
+    }
+
+    /// Hands the complete turns to the simulation, returns the last tick it may run
+    fn schedule_turns(&mut self, queue: &mut CommandQueue) -> u64 {
+        loop {
+            let turn = self.next_schedule_turn;
+
+            if turn >= INPUT_DELAY_TURNS {
+                let complete = self
+                    .turns
+                    .get(&turn)
+                    .map_or(false, |players| players.len() == self.players as usize);
+
+                if !complete {
+                    break;
+                }
+
+                // Same order on every peer
+                for (player, commands) in self.turns.remove(&turn).unwrap_or_default() {
+                    for command in commands {
+                        queue.schedule(Lockstep::first_tick(turn), PlayerId(player), command);
+                    }
+                }
+            }
+
+            self.next_schedule_turn += 1;
+        }
+
+        Lockstep::first_tick(self.next_schedule_turn) - 1
+    }
+
+    /// Compares a turn once every peer sent its checksum, the turn's checksums are dropped then.
+    /// Turns still missing checksums once they're `CHECKSUM_TIMEOUT_TURNS` old are dropped too.
+    fn check_desyncs(&mut self, tick: u64, desyncs: &mut Events<Desync>) {
+        let peers = self.players as usize - 1;
+        let checked: Vec<u64> = self
+            .local_checksums
+            .keys()
+            .filter(|turn| self.peer_checksums.get(turn).map_or(0, Vec::len) >= peers)
+            .cloned()
+            .collect();
+
+        for turn in checked {
+            let local_checksum = self.local_checksums.remove(&turn).unwrap_or_default();
+
+            for (player, checksum) in self.peer_checksums.remove(&turn).unwrap_or_default() {
+                if checksum != local_checksum {
+                    error!("Desync with player {} on turn {}", player, turn);
+                    self.last_desync = Some(Desync { turn, player });
+                    desyncs.send(Desync { turn, player });
+                }
+            }
+        }
+
+        let oldest_turn = (tick / TURN_TICKS).saturating_sub(CHECKSUM_TIMEOUT_TURNS);
+        let local_checksums = self.local_checksums.split_off(&oldest_turn);
+        for turn in std::mem::replace(&mut self.local_checksums, local_checksums).keys() {
+            warn!(
+                "Turn {} wasn't verified, checksums of some peers were lost",
+                turn
+            );
+        }
+        self.peer_checksums = self.peer_checksums.split_off(&oldest_turn);
+    }
+}
+
+fn lockstep_system(
+    tick: Res<Tick>,
+    mut lockstep: ResMut<Lockstep>,
+    mut queue: ResMut<CommandQueue>,
+    mut clock: ResMut<SimulationClock>,
+    mut desyncs: ResMut<Events<Desync>>,
+    mut reader: EventReader<PlayerCommand>,
+) {
+    for command in reader.iter() {
+        lockstep.local_commands.push(command.clone());
+    }
+
+    lockstep.receive();
+    lockstep.send_turns(tick.0);
+    clock.max_tick = Some(lockstep.schedule_turns(&mut queue));
+    lockstep.check_desyncs(tick.0, &mut desyncs);
+}
+
+fn turn_checksum_system(
+    tick: Res<Tick>,
+    mut lockstep: ResMut<Lockstep>,
+    units: Query<(&Owner, &Unit, &Position, &MoveOrder, &Health)>,
+    projectiles: Query<(&Projectile, &Position)>,
+) {
+    if tick.0 % TURN_TICKS != 0 {
+        return;
+    }
+
+    let turn = tick.0 / TURN_TICKS - 1;
+    let checksum = state_checksum(units.iter(), projectiles.iter());
+
+    lockstep.local_checksums.insert(turn, checksum);
+    let message = Message::Checksum {
+        player: lockstep.player,
+        turn,
+        checksum,
+    };
+    lockstep.send(&message);
+}
+
+/// Checksum of the units and the projectiles in flight, in simulation order
+pub fn state_checksum<'a>(
+    units: impl Iterator<Item = (&'a Owner, &'a Unit, &'a Position, &'a MoveOrder, &'a Health)>,
+    projectiles: impl Iterator<Item = (&'a Projectile, &'a Position)>,
+) -> u64 {
+    let mut checksum = Checksum::new();
+
+    for (owner, unit, position, move_order, health) in units {
+        checksum.write_u64(health.current as u64);
+        checksum.write_u64((owner.0).0 as u64);
+        checksum.write_u64(unit.selected as u64);
+        checksum.write_vector(unit.velocity);
+        checksum.write_vector(position.0);
+        checksum.write_u64(move_order.path.len() as u64);
+        for waypoint in move_order.path.iter() {
+            checksum.write_vector(*waypoint);
+        }
+    }
+
+    for (projectile, position) in projectiles {
+        checksum.write_u64(projectile.owner.0 as u64);
+        checksum.write_u64(projectile.damage as u64);
+        checksum.write_vector(position.0);
+        checksum.write_vector(projectile.destination);
+    }
+
+    checksum.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_harness::TestApp;
+
+    fn loopback_apps() -> (TestApp, TestApp) {
+        let mut transports = LoopbackTransport::network(2);
+        let second = transports.pop().unwrap();
+        let first = transports.pop().unwrap();
+
+        let first = TestApp::build(|app| {
+            app.insert_resource(Lockstep::new(0, 2, Box::new(first)))
+                .add_plugin(LockstepPlugin);
+        });
+        let second = TestApp::build(|app| {
+            app.insert_resource(Lockstep::new(1, 2, Box::new(second)))
+                .add_plugin(LockstepPlugin);
+        });
+
+        (first, second)
+    }
+
+    #[test]
+    fn test_loopback_simulations_stay_in_sync() {
+        let (mut first, mut second) = loopback_apps();
+
+        first.drag_select(Vec2::new(-120.0, -120.0), Vec2::new(-80.0, -80.0));
+        first.right_click(Vec2::new(-300.0, -250.0));
+
+        for _ in 0..300 {
+            first.step();
+            second.step();
+        }
+        while first.tick() < second.tick() {
+            first.step();
+        }
+        while second.tick() < first.tick() {
+            second.step();
+        }
+
+        assert!(first.tick() > 200);
+        assert_ne!(Vec2::new(-100.0, -100.0), first.unit_positions()[2]);
+        assert_eq!(first.unit_positions(), second.unit_positions());
+
+        for app in [&first, &second].iter() {
+            let lockstep = app.app.resources.get::<Lockstep>().unwrap();
+            assert_eq!(None, lockstep.last_desync);
+            // Only the last turns can still be waiting for the other player's checksum
+            assert!(lockstep.local_checksums.len() <= 2);
+            assert!(lockstep.peer_checksums.len() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_lost_checksums_are_dropped() {
+        let mut transports = LoopbackTransport::network(2);
+        let mut lockstep = Lockstep::new(0, 2, Box::new(transports.remove(0)));
+        let mut desyncs = Events::<Desync>::default();
+
+        // The other player's checksums never arrive
+        for turn in 0..100 {
+            lockstep.local_checksums.insert(turn, 42);
+            lockstep.check_desyncs((turn + 1) * TURN_TICKS, &mut desyncs);
+        }
+
+        assert!(lockstep.local_checksums.len() as u64 <= CHECKSUM_TIMEOUT_TURNS + 1);
+        assert_eq!(None, lockstep.last_desync);
+    }
+
+    #[test]
+    fn test_simulation_waits_for_peers() {
+        let (mut first, _second) = loopback_apps();
+
+        first.step_seconds(2.0);
+
+        // Only the turns nobody can send commands for run without the other player
+        assert_eq!(INPUT_DELAY_TURNS * TURN_TICKS, first.tick());
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+
+// Big enough for a turn worth of commands
+const MAX_PACKET_SIZE: usize = 64 * 1024;
+
+/// Unreliable, unordered delivery of packets to every peer
+pub trait Transport: Send + Sync {
+    fn broadcast(&mut self, packet: &[u8]);
+    fn receive(&mut self) -> Vec<Vec<u8>>;
+}
+
+/// Plain UDP, every peer is addressed directly
+pub struct UdpTransport {
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+}
+
+impl UdpTransport {
+    pub fn bind(address: SocketAddr, peers: Vec<SocketAddr>) -> io::Result<UdpTransport> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpTransport { socket, peers })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn broadcast(&mut self, packet: &[u8]) {
+        for peer in self.peers.iter() {
+            // Lost packets are sent again by the lockstep layer
+            if let Err(e) = self.socket.send_to(packet, peer) {
+                warn!("Failed to send packet to {}: {}", peer, e);
+            }
+        }
+    }
+
+    fn receive(&mut self) -> Vec<Vec<u8>> {
+        let mut packets = vec![];
+        let mut buffer = vec![0; MAX_PACKET_SIZE];
+
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((size, from)) => {
+                    if self.peers.contains(&from) {
+                        packets.push(buffer[..size].to_vec());
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Failed to receive packet: {}", e);
+                    break;
+                }
+            }
+        }
+
+        packets
+    }
+}
+
+type Inbox = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+/// In memory transport, to run several simulations in one process without a network
+pub struct LoopbackTransport {
+    inbox: Inbox,
+    peers: Vec<Inbox>,
+}
+
+impl LoopbackTransport {
+    /// One transport per player, all connected to each other
+    pub fn network(players: usize) -> Vec<LoopbackTransport> {
+        let inboxes: Vec<Inbox> = (0..players).map(|_| Inbox::default()).collect();
+
+        (0..players)
+            .map(|player| LoopbackTransport {
+                inbox: inboxes[player].clone(),
+                peers: inboxes
+                    .iter()
+                    .enumerate()
+                    .filter(|(peer, _)| *peer != player)
+                    .map(|(_, inbox)| inbox.clone())
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn broadcast(&mut self, packet: &[u8]) {
+        for peer in self.peers.iter() {
+            peer.lock().unwrap().push_back(packet.to_vec());
+        }
+    }
+
+    fn receive(&mut self) -> Vec<Vec<u8>> {
+        self.inbox.lock().unwrap().drain(..).collect()
+    }
+}
@@ -1,71 +1,208 @@
 mod animation;
+mod archetype;
+mod camera;
+mod checksum;
+mod combat;
+mod command;
+mod headless;
+mod lockstep;
 mod map_setup;
+mod math;
 mod mouse_position;
 mod movement;
+mod net;
+mod orders;
 mod path_finding;
+mod player;
+mod plugins;
+mod projectile;
+mod replay;
+mod save;
+mod script;
 mod selection_box;
+mod simulation;
+mod spatial;
+#[cfg(test)]
+mod test_harness;
 mod tiled;
 mod unit;
 
+use std::path::Path;
+
 use bevy::prelude::*;
-use bevy::render::camera::Camera;
 
-use animation::AnimationPlugin;
-use mouse_position::MousePositionPlugin;
+use archetype::{UnitArchetypes, UNITS_DIRECTORY};
+use headless::HeadlessOptions;
+use lockstep::{Lockstep, LockstepPlugin, UdpTransport};
+use net::{Client, ClientPlugin, Server};
+use orders::OrderInputPlugin;
 use path_finding::grid::Grid;
+use plugins::{PresentationPlugins, SimulationPlugins};
+use replay::{Replay, ReplayControlsPlugin, ReplayPlaybackPlugin, ReplayRecorderPlugin};
+use save::SaveInputPlugin;
 use selection_box::SelectionBoxPlugin;
-use unit::UnitPlugin;
+use simulation::{MapPath, SimulationClock};
 
-use movement::MovementPlugin;
+const DEFAULT_MAP: &str = "assets/basic_map.tmj";
 
 fn main() {
-    let map = tiled::Map::from_json_file("assets/basic_map.json").expect("Couldnt load map");
-    let path_finding_grid = Grid::from_tiled_map(&map).expect("Failed to generate collision grid");
-
-    App::build()
-        .insert_resource(WindowDescriptor {
-            title: "Simple RTS Demo".to_string(),
-            width: 1024.,
-            height: 1024.,
-            vsync: false,
-            ..Default::default()
-        })
-        .insert_resource(path_finding_grid)
-        .insert_resource(map)
-        .add_plugin(MovementPlugin)
-        .add_plugins(DefaultPlugins)
-        .add_plugin(MousePositionPlugin)
-        .add_plugin(SelectionBoxPlugin)
-        .add_plugin(UnitPlugin)
-        .add_plugin(AnimationPlugin)
-        .add_startup_system(setup.system())
-        .add_startup_system(map_setup::setup.system())
-        .add_system(camera_movement.system())
-        .run();
-}
+    let args: Vec<String> = std::env::args().collect();
 
-fn setup(commands: &mut Commands) {
-    commands
-        // cameras
-        .spawn(OrthographicCameraBundle::new_2d());
-}
-
-fn camera_movement(keys: Res<Input<KeyCode>>, mut query: Query<(&Camera, &mut Transform)>) {
-    if let Some((_camera, mut transform)) = query.iter_mut().next() {
-        if keys.pressed(KeyCode::Right) {
-            transform.translation.x += 6.0
+    // Every mode spawns units from these, the reload plugin only watches them once they're valid
+    let archetypes = match UnitArchetypes::load_directory(UNITS_DIRECTORY) {
+        Ok(archetypes) => archetypes,
+        Err(e) => {
+            eprintln!("Couldn't load unit archetypes: {}", e);
+            std::process::exit(1);
         }
+    };
+
+    if args.iter().any(|arg| arg == "--headless") {
+        let options = HeadlessOptions::from_args(&args).expect("Invalid headless options");
+        let (map, grid) = load_map(&options.map);
+        headless::run(options, archetypes, map, grid);
+        return;
+    }
+
+    // --server <address> runs a dedicated server
+    if let Some(address) = arg_value(&args, "--server") {
+        let address = address.parse().expect("Invalid --server address");
+        let server = Server::bind(address).expect("Couldnt bind server socket");
+        let (map, grid) = load_map(DEFAULT_MAP);
+        net::run_server(DEFAULT_MAP, server, archetypes, map, grid);
+        return;
+    }
+
+    // --connect <address> [--bind <address>] plays on a server
+    let client = arg_value(&args, "--connect").map(|server| {
+        let server = server.parse().expect("Invalid --connect address");
+        let bind = arg_value(&args, "--bind")
+            .unwrap_or_else(|| "0.0.0.0:0".to_string())
+            .parse()
+            .expect("Invalid --bind address");
+        Client::bind(bind, server).expect("Couldnt bind client socket")
+    });
+
+    // --lockstep <player> --bind <address> --peers <address,...> plays over UDP
+    let lockstep = arg_value(&args, "--lockstep").map(|player| {
+        let player: u8 = player.parse().expect("Invalid lockstep player");
+        let bind = arg_value(&args, "--bind")
+            .expect("Missing --bind")
+            .parse()
+            .expect("Invalid --bind address");
+        let peers: Vec<_> = arg_value(&args, "--peers")
+            .expect("Missing --peers")
+            .split(',')
+            .map(|peer| peer.parse().expect("Invalid --peers address"))
+            .collect();
+        let players = peers.len() as u8 + 1;
+        let transport = UdpTransport::bind(bind, peers).expect("Couldnt bind UDP socket");
+        Lockstep::new(player, players, Box::new(transport))
+    });
+
+    // --record <path> saves the game's commands, --replay <path> plays them back
+    let record = arg_value(&args, "--record");
+    let replay = arg_value(&args, "--replay")
+        .map(|path| Replay::from_json_file(&path).expect("Couldnt load replay"));
+    // --map <path> plays on another map, replays are played on theirs
+    let map_path = match &replay {
+        Some(replay) => replay.map.clone(),
+        None => arg_value(&args, "--map").unwrap_or_else(|| DEFAULT_MAP.to_string()),
+    };
+
+    // The asset server loads the map, the game waits on an empty one until it's there
+    let extension = Path::new(&map_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("");
+    if !tiled::TiledMapLoader::EXTENSIONS.contains(&extension) {
+        eprintln!(
+            "Couldn't load map {}: it isn't a .tmx or .tmj file",
+            map_path
+        );
+        std::process::exit(1);
+    }
 
-        if keys.pressed(KeyCode::Left) {
-            transform.translation.x -= 6.0
+    let mut app = App::build();
+    app.insert_resource(WindowDescriptor {
+        title: "Simple RTS Demo".to_string(),
+        width: 1024.,
+        height: 1024.,
+        vsync: false,
+        ..Default::default()
+    })
+    .insert_resource(MapPath(map_path.clone()))
+    .insert_resource(tiled::Map::default())
+    .insert_resource(Grid::default())
+    .insert_resource(archetypes)
+    .add_plugins(DefaultPlugins);
+
+    if let Some(client) = client {
+        // The server runs the simulation and owns the saves
+        app.insert_resource(client)
+            .add_plugin(ClientPlugin)
+            .add_plugins_with(PresentationPlugins, |group| {
+                group.disable::<SaveInputPlugin>()
+            })
+            .run();
+        return;
+    }
+
+    app.insert_resource(SimulationClock {
+        loading_map: true,
+        ..Default::default()
+    })
+    .add_plugins(SimulationPlugins);
+
+    if let Some(replay) = replay {
+        // Players only watch replays
+        app.add_plugins_with(PresentationPlugins, |group| {
+            group
+                .disable::<SelectionBoxPlugin>()
+                .disable::<OrderInputPlugin>()
+        })
+        .add_plugin(ReplayPlaybackPlugin { replay })
+        .add_plugin(ReplayControlsPlugin);
+    } else {
+        app.add_plugins(PresentationPlugins);
+
+        if let Some(path) = record {
+            app.add_plugin(ReplayRecorderPlugin {
+                map: map_path,
+                path: Some(path),
+            });
         }
+    }
 
-        if keys.pressed(KeyCode::Up) {
-            transform.translation.y += 6.0
+    if let Some(lockstep) = lockstep {
+        app.insert_resource(lockstep).add_plugin(LockstepPlugin);
+    }
+
+    app.run();
+}
+
+/// The headless and server modes run without an asset server, they read the map themselves
+fn load_map(path: &str) -> (tiled::Map, Grid) {
+    let map = match tiled::Map::from_file(path) {
+        Ok(map) => map,
+        Err(e) => {
+            eprintln!("Couldn't load map {}: {}", path, e);
+            std::process::exit(1);
         }
+    };
 
-        if keys.pressed(KeyCode::Down) {
-            transform.translation.y -= 6.0
+    match Grid::from_tiled_map(&map) {
+        Ok(grid) => (map, grid),
+        Err(e) => {
+            eprintln!("Couldn't generate the collision grid of {}: {:?}", path, e);
+            std::process::exit(1);
         }
     }
 }
+
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
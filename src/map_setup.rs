@@ -1,58 +1,603 @@
-use crate::tiled::Map;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::animation::TimedAnimation;
+use crate::combat::Dying;
+use crate::orders::order_move;
+use crate::path_finding::grid::Grid;
+use crate::simulation::{MapLoaded, MapPath};
+use crate::tiled::{Bounds, Map, TileLayer, TileRef, TileSet, TiledMapLoader};
+use crate::unit::{MoveOrder, Position, Unit};
+use bevy::app::AppExit;
+use bevy::asset::{AssetServerSettings, LoadState};
 use bevy::prelude::*;
+use bevy::render::camera::OrthographicProjection;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::render::pipeline::PrimitiveTopology;
+use bevy::sprite::SpriteResizeMode;
+
+/// Width and height of the blocks of tiles drawn as one mesh per tileset
+const CHUNK_SIZE: i32 = 16;
+/// Tiles around the view that are spawned too, so tiles bigger than the grid don't pop in
+const VIEW_MARGIN: i32 = 2;
+
+/// Loads the map at `MapPath` with the asset server, draws it, and reloads it when its file
+/// changes. Expects an empty `Map` and path finding `Grid` as resources, they're replaced once
+/// the map is loaded and `MapLoaded` is sent.
+pub struct MapSetupPlugin;
+
+impl Plugin for MapSetupPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        if !app.resources().contains::<Events<MapLoaded>>() {
+            app.add_event::<MapLoaded>();
+        }
+
+        app.add_asset::<Map>()
+            .init_asset_loader::<TiledMapLoader>()
+            .add_startup_system(setup.system())
+            .add_system(load_failed_system.system())
+            .add_system(load_system.system())
+            .add_system(chunk_visibility_system.system())
+            .add_system(tile_animation_system.system());
+    }
+}
+
+/// The map the asset server loads, and watches for changes
+struct MapAsset {
+    handle: Handle<Map>,
+    /// Swapped in for the empty `Map` the game started with
+    loaded: bool,
+}
+
+/// One material per tileset, in the order of `Map::tilesets`
+struct TileSetMaterials(Vec<Handle<ColorMaterial>>);
+
+/// Animations of the animated tiles, by tileset index and tile id in that tileset
+struct TileAnimations(HashMap<(usize, i32), TimedAnimation>);
 
-pub fn setup(
+/// The map is drawn by blocks of tiles, only the ones in view are spawned
+struct MapChunks(Vec<MapChunk>);
+
+struct MapChunk {
+    /// Index in `Map::tile_layers`
+    layer: usize,
+    bounds: Bounds,
+    /// A mesh per tileset used in the chunk, empty while out of view
+    meshes: Vec<Entity>,
+    spawned: bool,
+}
+
+/// Vertices of the tiles of a chunk that come from the same tileset
+#[derive(Default)]
+struct TileMesh {
+    positions: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    indices: Vec<u32>,
+    animated: Vec<AnimatedTile>,
+}
+
+/// On the chunk meshes showing animated tiles, their texture coordinates follow the animation
+struct AnimatedTiles {
+    tileset: usize,
+    tiles: Vec<AnimatedTile>,
+}
+
+struct AnimatedTile {
+    /// Index of its quad in the mesh
+    quad: usize,
+    id: i32,
+    tile: TileRef,
+    /// Tile id of the frame the quad shows, None until the animation starts
+    shown: Option<i32>,
+}
+
+impl TileMesh {
+    /// `corners` and `uvs` go around the quad counter clockwise
+    fn push_quad(&mut self, corners: [Vec2; 4], uvs: [[f32; 2]; 4]) {
+        let first = self.positions.len() as u32;
+
+        self.positions
+            .extend(corners.iter().map(|corner| [corner.x, corner.y, 0.0]));
+        self.uvs.extend_from_slice(&uvs);
+        self.indices
+            .extend([0, 1, 2, 0, 2, 3].iter().map(|index| first + index));
+    }
+
+    fn into_mesh(self) -> Mesh {
+        let normals = vec![[0.0, 0.0, 1.0]; self.positions.len()];
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+fn setup(
     commands: &mut Commands,
     asset_server: Res<AssetServer>,
-    map: Res<Map>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    settings: Res<AssetServerSettings>,
+    map_path: Res<MapPath>,
 ) {
-    let map_width = (map.width * map.tile_width) as f32;
-    let map_height = (map.height * map.tile_height) as f32;
-
-    // Assuming only one tileset for now
-    let tile_set_path = &map.tilesets[0].image;
-    let texture_handle = asset_server.load(tile_set_path.as_str());
-    let texture_atlas = TextureAtlas::from_grid(
-        texture_handle,
-        Vec2::new(map.tile_width as f32, map.tile_height as f32),
-        map.tilesets[0].columns as usize,
-        (map.tilesets[0].tile_count / map.tilesets[0].columns) as usize,
-    );
-    let texture_atlas_handle = texture_atlases.add(texture_atlas);
-
-    for layer in map.layers.iter() {
-        for y in 0..layer.height {
-            for x in 0..layer.width {
-                let sprite_index = layer.data[(y * layer.width + x) as usize] - 1;
-
-                if sprite_index == -1 {
-                    continue;
+    // Nothing to draw until the map is loaded
+    commands.insert_resource(TileSetMaterials(vec![]));
+    commands.insert_resource(TileAnimations(HashMap::new()));
+    commands.insert_resource(MapChunks(vec![]));
+
+    if let Err(e) = asset_server.watch_for_changes() {
+        warn!("Map changes won't be reloaded: {}", e);
+    }
+
+    // Asset paths are relative to the asset folder, maps elsewhere are loaded by their full path
+    let path = Path::new(&map_path.0);
+    let path = match path.strip_prefix(&settings.asset_folder) {
+        Ok(path) => path.to_path_buf(),
+        Err(_) => std::env::current_dir()
+            .map(|directory| directory.join(path))
+            .unwrap_or_else(|_| path.to_path_buf()),
+    };
+    commands.insert_resource(MapAsset {
+        handle: asset_server.load(path),
+        loaded: false,
+    });
+}
+
+/// The game can't start without its map, the asset server logged why it couldn't be loaded
+fn load_failed_system(
+    asset_server: Res<AssetServer>,
+    map_path: Res<MapPath>,
+    map_asset: Res<MapAsset>,
+    mut app_exit: ResMut<Events<AppExit>>,
+) {
+    if !map_asset.loaded && asset_server.get_load_state(&map_asset.handle) == LoadState::Failed {
+        error!("Couldn't load map {}", map_path.0);
+        app_exit.send(AppExit);
+    }
+}
+
+/// Swaps in the map once it's loaded, and again whenever its file changes. Its tiles are drawn
+/// again and the path finding grid is rebuilt from the map, which undoes the changes the game
+/// made to the grid since. The units on the move look for a path on the new grid.
+/// Peers in a lockstep game don't reload together.
+#[allow(clippy::too_many_arguments)]
+fn load_system(
+    commands: &mut Commands,
+    mut events: EventReader<AssetEvent<Map>>,
+    asset_server: Res<AssetServer>,
+    maps: Res<Assets<Map>>,
+    mut map_asset: ResMut<MapAsset>,
+    mut map: ResMut<Map>,
+    mut grid: ResMut<Grid>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut tileset_materials: ResMut<TileSetMaterials>,
+    mut animations: ResMut<TileAnimations>,
+    mut chunks: ResMut<MapChunks>,
+    mut map_loaded: ResMut<Events<MapLoaded>>,
+    mut app_exit: ResMut<Events<AppExit>>,
+    mut units: Query<(&Position, &mut Unit, &mut MoveOrder), Without<Dying>>,
+) {
+    for event in events.iter() {
+        let loaded = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle }
+                if *handle == map_asset.handle =>
+            {
+                match maps.get(handle) {
+                    Some(loaded) => loaded,
+                    None => continue,
                 }
+            }
+            _ => continue,
+        };
 
-                // Tiled renders top down
-                let real_y = layer.height - y - 1;
+        if map_asset.loaded && *loaded == *map {
+            continue;
+        }
 
-                let translation = Vec3::new(
-                    (x * map.tile_width) as f32 + (map.tile_width / 2) as f32 - (map_width / 2.0),
-                    (real_y * map.tile_height) as f32 + (map.tile_height / 2) as f32
-                        - (map_height / 2.0),
-                    layer.id as f32,
+        match Grid::from_tiled_map(loaded) {
+            Ok(loaded_grid) => *grid = loaded_grid,
+            Err(e) => {
+                error!(
+                    "Failed to load map, couldn't generate collision grid: {:?}",
+                    e
                 );
+                // The previous map is kept, unless there's none yet
+                if !map_asset.loaded {
+                    app_exit.send(AppExit);
+                }
+                continue;
+            }
+        }
+        *map = loaded.clone();
 
-                commands.spawn(SpriteSheetBundle {
-                    texture_atlas: texture_atlas_handle.clone(),
-                    sprite: TextureAtlasSprite {
-                        index: sprite_index as u32,
-                        ..Default::default()
-                    },
-                    transform: Transform {
-                        translation,
-                        ..Default::default()
+        for chunk in chunks.0.iter() {
+            for mesh in chunk.meshes.iter() {
+                commands.despawn(*mesh);
+            }
+        }
+
+        let tiles = map_tiles(&map, &asset_server, &mut materials);
+        *tileset_materials = tiles.0;
+        *animations = tiles.1;
+        *chunks = tiles.2;
+
+        if !map_asset.loaded {
+            // The units are spawned and the simulation starts
+            map_asset.loaded = true;
+            map_loaded.send(MapLoaded);
+            info!("Loaded map");
+            continue;
+        }
+
+        // Their paths were found on the previous grid
+        for (position, mut unit, mut move_order) in units.iter_mut() {
+            if let Some(target) = move_order.path.last().copied() {
+                order_move(&grid, position, target, &mut unit, &mut move_order);
+            }
+        }
+
+        info!("Reloaded map");
+    }
+}
+
+/// Nothing is spawned yet, `chunk_visibility_system` spawns the chunks in view
+fn map_tiles(
+    map: &Map,
+    asset_server: &AssetServer,
+    materials: &mut Assets<ColorMaterial>,
+) -> (TileSetMaterials, TileAnimations, MapChunks) {
+    // Tiles are drawn with the texture of the tileset their global id belongs to
+    let tileset_materials = map
+        .tilesets
+        .iter()
+        .map(|tileset| {
+            let texture_handle = asset_server.load(tileset.image.as_str());
+            materials.add(ColorMaterial::texture(texture_handle))
+        })
+        .collect();
+
+    let mut chunks = vec![];
+    for (index, (layer, tiles)) in map.tile_layers().into_iter().enumerate() {
+        if !layer.visible {
+            continue;
+        }
+
+        for chunk in tiles.chunks.iter() {
+            chunks.extend(
+                chunk
+                    .bounds()
+                    .split(CHUNK_SIZE)
+                    .into_iter()
+                    .map(|bounds| MapChunk {
+                        layer: index,
+                        bounds,
+                        meshes: vec![],
+                        spawned: false,
+                    }),
+            );
+        }
+    }
+
+    let mut animations = HashMap::new();
+    for (index, tileset) in map.tilesets.iter().enumerate() {
+        for tile in tileset
+            .tiles
+            .iter()
+            .filter(|tile| !tile.animation.is_empty())
+        {
+            let frames = tile
+                .animation
+                .iter()
+                .map(|frame| (frame.tile_id as usize, frame.duration as f32 / 1000.0))
+                .collect();
+            animations.insert((index, tile.id), TimedAnimation::new(frames));
+        }
+    }
+
+    (
+        TileSetMaterials(tileset_materials),
+        TileAnimations(animations),
+        MapChunks(chunks),
+    )
+}
+
+/// Spawns the meshes of the chunks coming into view, and despawns the ones leaving it
+fn chunk_visibility_system(
+    commands: &mut Commands,
+    map: Res<Map>,
+    materials: Res<TileSetMaterials>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut chunks: ResMut<MapChunks>,
+    cameras: Query<(&GlobalTransform, &OrthographicProjection)>,
+) {
+    // The map is still loading
+    if chunks.0.is_empty() {
+        return;
+    }
+
+    let view = match cameras.iter().next() {
+        Some((global_transform, projection)) => {
+            let top_left = global_transform
+                .mul_vec3(Vec3::new(projection.left, projection.top, 0.0))
+                .truncate();
+            let bottom_right = global_transform
+                .mul_vec3(Vec3::new(projection.right, projection.bottom, 0.0))
+                .truncate();
+            let map_projection = map.projection();
+            let (left, top) = map_projection.tile_position(top_left);
+            let (right, bottom) = map_projection.tile_position(bottom_right);
+
+            Bounds {
+                x: left - VIEW_MARGIN,
+                y: top - VIEW_MARGIN,
+                width: right - left + 1 + 2 * VIEW_MARGIN,
+                height: bottom - top + 1 + 2 * VIEW_MARGIN,
+            }
+        }
+        None => return,
+    };
+
+    let layers = map.tile_layers();
+
+    for chunk in chunks.0.iter_mut() {
+        let visible = chunk.bounds.intersects(&view);
+
+        if visible && !chunk.spawned {
+            let (layer, tiles) = layers[chunk.layer];
+            // Offsets are in pixels down, as are the world units but up
+            let translation = Vec3::new(layer.offset_x, -layer.offset_y, layer.layer.id as f32);
+            chunk.spawned = true;
+
+            for (tileset_index, mut tile_mesh) in chunk_meshes(&map, tiles, chunk.bounds) {
+                let animated = std::mem::take(&mut tile_mesh.animated);
+
+                commands.spawn(SpriteBundle {
+                    mesh: meshes.add(tile_mesh.into_mesh()),
+                    material: materials.0[tileset_index].clone(),
+                    // The vertices are already in world units
+                    sprite: Sprite {
+                        size: Vec2::one(),
+                        resize_mode: SpriteResizeMode::Manual,
                     },
+                    transform: Transform::from_translation(translation),
                     ..Default::default()
                 });
+                if !animated.is_empty() {
+                    commands.with(AnimatedTiles {
+                        tileset: tileset_index,
+                        tiles: animated,
+                    });
+                }
+                chunk.meshes.extend(commands.current_entity());
+            }
+        } else if !visible && chunk.spawned {
+            chunk.spawned = false;
+
+            for mesh in chunk.meshes.drain(..) {
+                commands.despawn(mesh);
             }
         }
     }
 }
+
+/// Shows the current frame of the animated tiles, every tile with the same animation is in sync
+fn tile_animation_system(
+    time: Res<Time>,
+    map: Res<Map>,
+    animations: Res<TileAnimations>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut query: Query<(&mut AnimatedTiles, &Handle<Mesh>)>,
+) {
+    let elapsed = time.seconds_since_startup() as f32;
+
+    for (mut animated_tiles, mesh) in query.iter_mut() {
+        let tileset_index = animated_tiles.tileset;
+        // Left over from before the map was reloaded, they're being despawned
+        let tileset = match map.tilesets.get(tileset_index) {
+            Some(tileset) => tileset,
+            None => continue,
+        };
+        let mut changes = vec![];
+
+        for animated in animated_tiles.tiles.iter_mut() {
+            let frame = match animations.0.get(&(tileset_index, animated.id)) {
+                Some(animation) => animation.sprite_index(elapsed) as i32,
+                None => continue,
+            };
+
+            if animated.shown != Some(frame) {
+                animated.shown = Some(frame);
+                changes.push((animated.quad, tile_uvs(tileset, frame, animated.tile)));
+            }
+        }
+
+        // Only touching the mesh when a frame changed, it's uploaded again when modified
+        if changes.is_empty() {
+            continue;
+        }
+
+        if let Some(mesh) = meshes.get_mut(mesh) {
+            if let Some(VertexAttributeValues::Float2(uvs)) =
+                mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0)
+            {
+                for (quad, quad_uvs) in changes {
+                    uvs[quad * 4..quad * 4 + 4].copy_from_slice(&quad_uvs);
+                }
+            }
+        }
+    }
+}
+
+/// A quad per tile of `tiles` in `bounds`, grouped by tileset
+fn chunk_meshes(map: &Map, tiles: &TileLayer, bounds: Bounds) -> Vec<(usize, TileMesh)> {
+    let mut tile_meshes: Vec<TileMesh> = map.tilesets.iter().map(|_| TileMesh::default()).collect();
+    let projection = map.projection();
+
+    for y in bounds.y..bounds.y + bounds.height {
+        for x in bounds.x..bounds.x + bounds.width {
+            let tile = tiles.tile(x, y);
+            let (tileset_index, id) = match map.resolve_gid(tile.gid) {
+                Some(resolved) => resolved,
+                None => continue,
+            };
+            let tileset = &map.tilesets[tileset_index];
+
+            // Tiles bigger than the grid are anchored to the bottom left of their cell
+            let bottom_left = projection.world_position(
+                (x * map.tile_width) as f32,
+                ((y + 1) * map.tile_height) as f32,
+            );
+            let size = Vec2::new(tileset.tile_width as f32, tileset.tile_height as f32);
+            let center = bottom_left + size / 2.0;
+            // Swapping the axes turns the quad too
+            let half = if tile.flip_d {
+                Vec2::new(size.y, size.x) / 2.0
+            } else {
+                size / 2.0
+            };
+
+            let tile_mesh = &mut tile_meshes[tileset_index];
+            let animated = tileset
+                .tile(id)
+                .map_or(false, |tile| !tile.animation.is_empty());
+            if animated {
+                tile_mesh.animated.push(AnimatedTile {
+                    quad: tile_mesh.positions.len() / 4,
+                    id,
+                    tile,
+                    shown: None,
+                });
+            }
+
+            tile_mesh.push_quad(
+                [
+                    center + Vec2::new(-half.x, -half.y),
+                    center + Vec2::new(half.x, -half.y),
+                    center + Vec2::new(half.x, half.y),
+                    center + Vec2::new(-half.x, half.y),
+                ],
+                tile_uvs(tileset, id, tile),
+            );
+        }
+    }
+
+    tile_meshes
+        .into_iter()
+        .enumerate()
+        .filter(|(_, tile_mesh)| !tile_mesh.positions.is_empty())
+        .collect()
+}
+
+/// Texture coordinates of the bottom left, bottom right, top right and top left corners of
+/// the tile `id` of `tileset`, flipped the way Tiled does
+fn tile_uvs(tileset: &TileSet, id: i32, tile: TileRef) -> [[f32; 2]; 4] {
+    let image_size = Vec2::new(tileset.image_width as f32, tileset.image_height as f32);
+    let tile_size = Vec2::new(tileset.tile_width as f32, tileset.tile_height as f32);
+    let top_left = Vec2::new(
+        (id % tileset.columns) as f32 * tile_size.x,
+        (id / tileset.columns) as f32 * tile_size.y,
+    ) / image_size;
+    let tile_size = tile_size / image_size;
+
+    // Same corners in the tile, the texture's y axis points down
+    let corners = [(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)];
+
+    let mut uvs = [[0.0; 2]; 4];
+    for (uv, &(x, y)) in uvs.iter_mut().zip(corners.iter()) {
+        // Undoes the flips from the last one applied, the diagonal one goes first
+        let (mut x, mut y) = (x, y);
+        if tile.flip_h {
+            x = 1.0 - x;
+        }
+        if tile.flip_v {
+            y = 1.0 - y;
+        }
+        if tile.flip_d {
+            std::mem::swap(&mut x, &mut y);
+        }
+
+        *uv = [top_left.x + x * tile_size.x, top_left.y + y * tile_size.y];
+    }
+
+    uvs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_uvs() {
+        let tileset: TileSet = serde_json::from_str(
+            r#"{"firstgid": 1, "columns": 2, "image": "a.png", "imageheight": 64,
+                "imagewidth": 64, "name": "a", "tilecount": 4, "tileheight": 32,
+                "tilewidth": 32}"#,
+        )
+        .expect("Failed to parse tileset");
+
+        let tile = TileRef::from(4);
+        assert_eq!(
+            [[0.5, 1.0], [1.0, 1.0], [1.0, 0.5], [0.5, 0.5]],
+            tile_uvs(&tileset, 3, tile)
+        );
+
+        let flipped = TileRef {
+            flip_h: true,
+            ..tile
+        };
+        assert_eq!(
+            [[1.0, 1.0], [0.5, 1.0], [0.5, 0.5], [1.0, 0.5]],
+            tile_uvs(&tileset, 3, flipped)
+        );
+
+        // The bottom left corner shows the top right of the tile once the axes are swapped
+        let diagonal = TileRef {
+            flip_d: true,
+            ..tile
+        };
+        assert_eq!([0.5, 0.0], tile_uvs(&tileset, 0, diagonal)[0]);
+        assert_eq!([0.0, 0.0], tile_uvs(&tileset, 0, diagonal)[3]);
+    }
+
+    #[test]
+    fn test_chunk_meshes() {
+        let map = Map::from_file("assets/basic_map.tmj").expect("Failed to load map");
+        let (_, tiles) = map.tile_layers()[0];
+        let bounds = Bounds {
+            x: 0,
+            y: 0,
+            width: CHUNK_SIZE,
+            height: CHUNK_SIZE,
+        };
+
+        let meshes = chunk_meshes(&map, tiles, bounds);
+        assert_eq!(1, meshes.len());
+
+        let (tileset, tile_mesh) = &meshes[0];
+        assert_eq!(0, *tileset);
+        // That corner of the map is fully painted
+        assert_eq!(256 * 4, tile_mesh.positions.len());
+        assert_eq!(256 * 6, tile_mesh.indices.len());
+        // The top left tile of the map is in the top left corner of the world
+        assert_eq!([-800.0, 768.0, 0.0], tile_mesh.positions[0]);
+        assert!(tile_mesh.animated.is_empty());
+    }
+
+    #[test]
+    fn test_animated_tiles() {
+        let map = Map::from_file("assets/test_maps/map.json").expect("Failed to load map");
+        let (_, details) = map.tile_layers()[1];
+        let bounds = details.bounds().expect("Empty layer");
+
+        let meshes = chunk_meshes(&map, details, bounds);
+        let animated: Vec<(usize, i32)> = meshes[0]
+            .1
+            .animated
+            .iter()
+            .map(|tile| (tile.quad, tile.id))
+            .collect();
+        // Both water tiles, the flipped one included
+        assert_eq!(vec![(0, 5), (1, 5)], animated);
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+use serde::{Deserialize, Serialize};
+
+const FRAC_BITS: u32 = 16;
+const ONE: i64 = 1 << FRAC_BITS;
+
+/// A signed 48.16 fixed-point number
+/// All arithmetic is done on integers, which makes results bit for bit identical on every machine,
+/// unlike floats where the compiler or the CPU might pick different instructions.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Fixed(i64);
+
+impl Fixed {
+    pub const fn from_bits(bits: i64) -> Self {
+        Fixed(bits)
+    }
+
+    pub const fn to_bits(self) -> i64 {
+        self.0
+    }
+
+    pub const fn from_int(value: i32) -> Self {
+        Fixed((value as i64) << FRAC_BITS)
+    }
+
+    /// Only meant for inputs (map data, mouse clicks), the conversion rounds to the nearest
+    /// representable value so the same float always gives the same fixed-point number.
+    pub fn from_f32(value: f32) -> Self {
+        Fixed((value as f64 * ONE as f64).round() as i64)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        (self.0 as f64 / ONE as f64) as f32
+    }
+
+    pub fn abs(self) -> Self {
+        Fixed(self.0.abs())
+    }
+
+    /// Integer square root, rounded down
+    pub fn sqrt(self) -> Self {
+        if self.0 <= 0 {
+            return Fixed(0);
+        }
+
+        // sqrt(x * 2^16) * 2^8 = sqrt(x * 2^32), which keeps the result in 48.16
+        let n = (self.0 as u128) << FRAC_BITS;
+        let mut result: u128 = 0;
+        let mut bit: u128 = 1 << 126;
+
+        while bit > n {
+            bit >>= 2;
+        }
+
+        let mut n = n;
+        while bit != 0 {
+            if n >= result + bit {
+                n -= result + bit;
+                result = (result >> 1) + bit;
+            } else {
+                result >>= 1;
+            }
+            bit >>= 2;
+        }
+
+        Fixed(result as i64)
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_f32())
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, other: Fixed) -> Fixed {
+        Fixed(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, other: Fixed) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, other: Fixed) -> Fixed {
+        Fixed(self.0 - other.0)
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, other: Fixed) {
+        self.0 -= other.0;
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, other: Fixed) -> Fixed {
+        Fixed(((self.0 as i128 * other.0 as i128) >> FRAC_BITS) as i64)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Fixed;
+
+    fn div(self, other: Fixed) -> Fixed {
+        Fixed((((self.0 as i128) << FRAC_BITS) / other.0 as i128) as i64)
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed(-self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic() {
+        let a = Fixed::from_f32(1.5);
+        let b = Fixed::from_int(2);
+
+        assert_eq!(Fixed::from_f32(3.5), a + b);
+        assert_eq!(Fixed::from_f32(-0.5), a - b);
+        assert_eq!(Fixed::from_int(3), a * b);
+        assert_eq!(Fixed::from_f32(0.75), a / b);
+    }
+
+    #[test]
+    fn test_sqrt() {
+        assert_eq!(Fixed::from_int(3), Fixed::from_int(9).sqrt());
+        assert_eq!(Fixed::from_f32(0.5), Fixed::from_f32(0.25).sqrt());
+        assert_eq!(Fixed::from_int(0), Fixed::from_int(-4).sqrt());
+    }
+}
@@ -0,0 +1,190 @@
+#[cfg(feature = "fixed-point")]
+mod fixed;
+
+use std::fmt;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "fixed-point")]
+pub use self::fixed::Fixed;
+
+/// The number type used by the simulation
+/// Defaults to f32, the `fixed-point` feature swaps it for a deterministic fixed-point type
+#[cfg(feature = "fixed-point")]
+pub type Scalar = Fixed;
+
+#[cfg(not(feature = "fixed-point"))]
+pub type Scalar = f32;
+
+/// Operations the simulation needs from a `Scalar`, whatever its representation
+pub trait Real:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    fn zero() -> Self;
+    fn from_int(value: i32) -> Self;
+    fn from_f32(value: f32) -> Self;
+    fn to_f32(self) -> f32;
+    fn sqrt(self) -> Self;
+    /// Raw bits, used to checksum the simulation state
+    fn checksum_bits(self) -> u64;
+}
+
+impl Real for f32 {
+    fn zero() -> Self {
+        0.0
+    }
+
+    fn from_int(value: i32) -> Self {
+        value as f32
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn sqrt(self) -> Self {
+        f32::sqrt(self)
+    }
+
+    fn checksum_bits(self) -> u64 {
+        f32::to_bits(self) as u64
+    }
+}
+
+#[cfg(feature = "fixed-point")]
+impl Real for Fixed {
+    fn zero() -> Self {
+        Fixed::from_bits(0)
+    }
+
+    fn from_int(value: i32) -> Self {
+        Fixed::from_int(value)
+    }
+
+    fn from_f32(value: f32) -> Self {
+        Fixed::from_f32(value)
+    }
+
+    fn to_f32(self) -> f32 {
+        Fixed::to_f32(self)
+    }
+
+    fn sqrt(self) -> Self {
+        Fixed::sqrt(self)
+    }
+
+    fn checksum_bits(self) -> u64 {
+        Fixed::to_bits(self) as u64
+    }
+}
+
+/// A 2D vector of `Scalar`s, the simulation counterpart of `Vec2`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Vector {
+    pub x: Scalar,
+    pub y: Scalar,
+}
+
+impl Vector {
+    pub fn new(x: Scalar, y: Scalar) -> Self {
+        Self { x, y }
+    }
+
+    pub fn zero() -> Self {
+        Self::new(Scalar::zero(), Scalar::zero())
+    }
+
+    pub fn length_squared(self) -> Scalar {
+        self.x * self.x + self.y * self.y
+    }
+
+    pub fn length(self) -> Scalar {
+        self.length_squared().sqrt()
+    }
+
+    pub fn to_vec2(self) -> Vec2 {
+        Vec2::new(self.x.to_f32(), self.y.to_f32())
+    }
+}
+
+impl From<Vec2> for Vector {
+    fn from(vec: Vec2) -> Self {
+        Self::new(Scalar::from_f32(vec.x), Scalar::from_f32(vec.y))
+    }
+}
+
+impl From<Vector> for Vec2 {
+    fn from(vector: Vector) -> Self {
+        vector.to_vec2()
+    }
+}
+
+impl fmt::Display for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", self.x, self.y)
+    }
+}
+
+impl Add for Vector {
+    type Output = Vector;
+
+    fn add(self, other: Vector) -> Vector {
+        Vector::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl AddAssign for Vector {
+    fn add_assign(&mut self, other: Vector) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Vector {
+    type Output = Vector;
+
+    fn sub(self, other: Vector) -> Vector {
+        Vector::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl SubAssign for Vector {
+    fn sub_assign(&mut self, other: Vector) {
+        *self = *self - other;
+    }
+}
+
+impl Mul<Scalar> for Vector {
+    type Output = Vector;
+
+    fn mul(self, scalar: Scalar) -> Vector {
+        Vector::new(self.x * scalar, self.y * scalar)
+    }
+}
+
+impl Div<Scalar> for Vector {
+    type Output = Vector;
+
+    fn div(self, scalar: Scalar) -> Vector {
+        Vector::new(self.x / scalar, self.y / scalar)
+    }
+}
+
+impl Neg for Vector {
+    type Output = Vector;
+
+    fn neg(self) -> Vector {
+        Vector::new(-self.x, -self.y)
+    }
+}
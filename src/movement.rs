@@ -1,180 +1,212 @@
 use std::collections::HashMap;
 
-use crate::animation::Animations;
-use crate::mouse_position::MouseWorldPosition;
-use crate::path_finding;
-use crate::path_finding::grid::Grid;
+use crate::math::{Real, Scalar, Vector};
+use crate::simulation::{timestep, SIMULATION_STAGE};
 use crate::unit::*;
 
 use bevy::prelude::*;
 
-use bevy::sprite::collide_aabb::collide;
+// Very simple collision handling.
+// TODO:
+//   - Actual collision component
+//   - Not only other units
 
 pub struct MovementPlugin;
 
 impl Plugin for MovementPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_system(order_system.system())
-            .add_system(velocity_system.system())
-            .add_system(animation_system.system())
-            .add_system(physics_system.system());
-    }
-}
-
-fn animation_system(mut query: Query<(&Unit, &mut Animations)>) {
-    for (unit, mut animations) in query.iter_mut() {
-        if unit.velocity > Vec2::zero() {
-            animations.play("idle".to_string());
-        } else {
-            animations.play("moving".to_string());
-        }
+        app.add_system_to_stage(SIMULATION_STAGE, velocity_system.system())
+            .add_system_to_stage(SIMULATION_STAGE, physics_system.system());
     }
 }
 
 fn physics_system(
-    time: Res<Time>,
     mut q: QuerySet<(
-        Query<(Entity, &Unit, &Transform, &MoveOrder)>,
-        Query<(Entity, &Unit, &mut Transform, &mut MoveOrder)>,
+        Query<(Entity, &Unit, &Position)>,
+        Query<(Entity, &mut Position, &mut MoveOrder)>,
     )>,
 ) {
-    let mut movements = HashMap::<Entity, Vec2>::new();
-
-    for (entity, unit, transform, _) in q.q0().iter() {
-        let new_translation = unit.velocity * time.delta_seconds();
-        let mut is_colliding = false;
-
-        for (other_entity, _, other_transform, _) in q.q0().iter() {
-            if entity == other_entity {
-                break;
-            }
-
-            // Very simple collision handling.
-            // TODO:
-            //   - Actual collision component
-            //   - Not only other units
-            //   - Actual sizes, hard coded to 32 rn
-            let collide_result = collide(
-                Vec3::new(
-                    transform.translation.x + new_translation.x,
-                    transform.translation.y + new_translation.y,
-                    transform.translation.z,
-                ),
-                Vec2::new(16.0, 24.0),
-                other_transform.translation,
-                Vec2::new(16.0, 24.0),
-            );
-
-            if collide_result.is_some() {
-                is_colliding = true;
-                break;
-            }
-        }
-
-        if !is_colliding {
-            movements.insert(entity, new_translation);
+    let (entities, bodies): (Vec<Entity>, Vec<Body>) = q
+        .q0()
+        .iter()
+        .map(|(entity, unit, position)| (entity, (position.0, unit.velocity, unit.size)))
+        .unzip();
+
+    let movements: HashMap<Entity, Vector> = entities
+        .into_iter()
+        .zip(resolve_movements(&bodies, timestep()))
+        .filter_map(|(entity, movement)| movement.map(|m| (entity, m)))
+        .collect();
+
+    for (entity, mut position, mut move_order) in q.q1_mut().iter_mut() {
+        if let Some(translation) = movements.get(&entity) {
+            follow_path(&mut position.0, &mut move_order.path, *translation);
         }
     }
+}
 
-    for (entity, _, mut transform, mut move_order) in q.q1_mut().iter_mut() {
+fn velocity_system(mut query: Query<(&mut Unit, &Position, &MoveOrder)>) {
+    for (mut unit, position, move_order) in query.iter_mut() {
         if let Some(order_coords) = move_order.path.get(0) {
-            if let Some(new_translation) = movements.get(&entity) {
-                transform.translation.x += new_translation.x;
-                transform.translation.y += new_translation.y;
-                info!("New Translation: {}", transform.translation);
-
-                let diff = transform.translation.truncate() - *order_coords;
-
-                // TODO: Is there a better solution to this? To avoid turning around until we find the
-                // precise point, when we're close enough we move to the next path segment
-                if diff.length() < 6.0 {
-                    move_order.path.remove(0);
-                }
-            }
+            unit.velocity = seek(&unit, position.0, *order_coords, timestep());
+        } else {
+            unit.velocity = Vector::zero();
         }
     }
 }
 
-fn velocity_system(time: Res<Time>, mut query: Query<(&mut Unit, &Transform, &MoveOrder)>) {
-    for (mut unit, transform, move_order) in query.iter_mut() {
-        if let Some(order_coords) = move_order.path.get(0) {
-            let desired = *order_coords - transform.translation.truncate();
+/// Steers the unit towards `target`, returns its new velocity
+pub fn seek(unit: &Unit, position: Vector, target: Vector, timestep: Scalar) -> Vector {
+    let desired = target - position;
+    let distance = desired.length();
+
+    if distance == Scalar::zero() {
+        return unit.velocity;
+    }
+
+    let desired_velocity = desired * (unit.max_speed / distance);
+    let force = desired_velocity - unit.velocity;
+    let seek = force * (unit.max_force / unit.max_speed);
 
-            if desired.length() != 0.0 {
-                let desired_velocity = desired * (unit.max_speed / desired.length());
-                let force = desired_velocity - unit.velocity;
-                let seek = force * (unit.max_force / unit.max_speed);
+    let mut velocity = unit.velocity + seek * timestep;
 
-                unit.velocity += seek * time.delta_seconds();
+    let speed = velocity.length();
 
-                let speed = unit.velocity.length();
+    if speed > unit.max_speed {
+        velocity = velocity * (Scalar::from_int(4) / speed);
+    }
+
+    velocity
+}
 
-                if speed > unit.max_speed {
-                    unit.velocity = unit.velocity * (4.0 / speed);
-                }
+/// Position, velocity and size of a unit
+pub type Body = (Vector, Vector, Vector);
+
+/// Computes how far each body moves this tick given its `(position, velocity, size)`,
+/// `None` when the move would collide with another body.
+pub fn resolve_movements(bodies: &[Body], timestep: Scalar) -> Vec<Option<Vector>> {
+    let two = Scalar::from_int(2);
+
+    bodies
+        .iter()
+        .enumerate()
+        .map(|(i, (position, velocity, size))| {
+            let new_translation = *velocity * timestep;
+            let new_position = *position + new_translation;
+
+            let is_colliding = bodies[..i].iter().any(|(other_position, _, other_size)| {
+                let diff = new_position - *other_position;
+                let width = (size.x + other_size.x) / two;
+                let height = (size.y + other_size.y) / two;
+                diff.x < width && diff.x > -width && diff.y < height && diff.y > -height
+            });
+
+            if is_colliding {
+                None
+            } else {
+                Some(new_translation)
             }
-        } else {
-            unit.velocity = Vec2::zero();
+        })
+        .collect()
+}
+
+/// Moves along the path, dropping the waypoint once it's reached
+pub fn follow_path(position: &mut Vector, path: &mut Vec<Vector>, translation: Vector) {
+    if let Some(order_coords) = path.get(0) {
+        *position += translation;
+        debug!("New Position: {}", position);
+
+        let diff = *position - *order_coords;
+
+        // TODO: Is there a better solution to this? To avoid turning around until we find the
+        // precise point, when we're close enough we move to the next path segment
+        if diff.length() < Scalar::from_int(6) {
+            path.remove(0);
         }
     }
 }
 
-fn order_system(
-    commands: &mut Commands,
-    mouse_buttons: Res<Input<MouseButton>>,
-    mouse_position: Res<MouseWorldPosition>,
-    grid: Res<Grid>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    mut query: Query<(&Transform, &mut Unit, &mut MoveOrder)>,
-) {
-    if mouse_buttons.just_pressed(MouseButton::Right) {
-        for (transform, mut unit, mut move_order) in query.iter_mut() {
-            if unit.selected {
-                let blue = materials.add(Color::rgba(0.0, 0.0, 255.0, 0.2).into());
-                let red = materials.add(Color::rgba(255.0, 0.0, 0.0, 0.2).into());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combat::Health;
+    use crate::lockstep::state_checksum;
+    use crate::player::{Owner, PlayerId};
+    use crate::projectile::Projectile;
+    use crate::test_harness::TestApp;
+
+    /// State after 240 ticks of `simulate`, any change to it means the simulation
+    /// no longer gives the same results as before (and as other machines).
+    #[cfg(feature = "fixed-point")]
+    const GOLDEN_CHECKSUM: u64 = 2033509800902732325;
+    /// Floats only give the same results on machines and builds doing the same operations
+    #[cfg(not(feature = "fixed-point"))]
+    const GOLDEN_CHECKSUM: u64 = 1866931643467613546;
+
+    fn vector(x: f32, y: f32) -> Vector {
+        Vector::from(Vec2::new(x, y))
+    }
 
-                debug_path(commands, transform, &mouse_position, &grid, blue, red);
+    /// Runs the movement systems on units that don't fight, with the lockstep checksum
+    fn simulate(ticks: usize) -> u64 {
+        let mut app = TestApp::new();
 
-                let mut best_path = path_finding::find_path(
-                    Vec2::from(transform.translation),
-                    Vec2::from(mouse_position.0),
-                    &grid,
-                );
+        // Without the map's units, which would fight
+        let world = &mut app.app.world;
+        let mut spawned: Vec<Entity> = world.query_filtered::<Entity, With<Unit>>().collect();
+        spawned.extend(world.query_filtered::<Entity, With<Projectile>>());
+        for entity in spawned {
+            world.despawn(entity).expect("Failed to despawn");
+        }
 
-                let black = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.2).into());
-                path_finding::draw_funnel_path(best_path.clone(), commands, black);
+        let units = vec![
+            (
+                vector(100.0, 100.0),
+                vec![vector(-200.0, 150.0), vector(-300.0, -50.0)],
+            ),
+            (vector(0.0, 0.0), vec![vector(250.0, 0.0)]),
+            (
+                vector(-100.0, -100.0),
+                vec![vector(-100.0, 200.0), vector(100.0, 220.0)],
+            ),
+            (vector(23.0, 42.0), vec![vector(23.0, -300.0)]),
+        ];
+        for (position, path) in units {
+            world.spawn((
+                Owner(PlayerId(0)),
+                MoveOrder { path },
+                Position(position),
+                Unit {
+                    archetype: "dino".to_string(),
+                    selected: false,
+                    velocity: Vector::zero(),
+                    max_speed: Scalar::from_int(100),
+                    max_force: Scalar::from_int(250),
+                    size: Vector::new(Scalar::from_int(16), Scalar::from_int(24)),
+                    facing: Facing::Right,
+                },
+                Health::new(100),
+            ));
+        }
 
-                unit.velocity = Vec2::zero();
+        for _ in 0..ticks {
+            app.step();
+        }
 
-                // We're here already
-                best_path.remove(0);
+        let world = &app.app.world;
+        state_checksum(
+            world.query::<(&Owner, &Unit, &Position, &MoveOrder, &Health)>(),
+            world.query::<(&Projectile, &Position)>(),
+        )
+    }
 
-                move_order.path = best_path
-            }
-        }
+    #[test]
+    fn test_simulation_is_repeatable() {
+        assert_eq!(simulate(240), simulate(240));
     }
-}
 
-fn debug_path(
-    commands: &mut Commands,
-    transform: &Transform,
-    mouse_position: &MouseWorldPosition,
-    grid: &Grid,
-    blue: Handle<ColorMaterial>,
-    red: Handle<ColorMaterial>,
-) {
-    let astar_path = path_finding::astar(
-        Vec2::from(transform.translation),
-        Vec2::from(mouse_position.0),
-        &grid,
-    );
-
-    path_finding::draw_astar_path(astar_path, commands, blue);
-
-    let portals = path_finding::funnel_portals(
-        Vec2::from(transform.translation),
-        Vec2::from(mouse_position.0),
-        &grid,
-    );
-    path_finding::draw_funnel_portals(portals, commands, red);
+    #[test]
+    fn test_simulation_matches_golden_checksum() {
+        assert_eq!(GOLDEN_CHECKSUM, simulate(240));
+    }
 }
@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+
+use bevy::app::stage;
+use bevy::prelude::*;
+
+use super::{
+    ClientInput, NetworkId, ProjectileState, Snapshot, SnapshotPart, Socket, UnitState,
+    HISTORY_TICKS, INTERPOLATION_TICKS,
+};
+use crate::combat::{Dying, Health};
+use crate::command::PlayerCommand;
+use crate::math::Vector;
+use crate::player::{LocalPlayer, Players};
+use crate::projectile::Projectile;
+use crate::simulation::TICKS_PER_SECOND;
+use crate::unit::{MoveOrder, Position, Unit};
+
+// Enough to cover the interpolation delay, with a few lost snapshots
+const MAX_SAMPLES: usize = 8;
+
+type UnitQuery<'a> = (
+    &'a mut Unit,
+    &'a mut MoveOrder,
+    &'a mut Health,
+    Option<&'a Dying>,
+    &'a mut Interpolation,
+);
+
+type ProjectileQuery<'a> = (&'a mut Projectile, &'a mut Interpolation);
+
+/// Sends the player's commands to a server and shows the units it sends back
+/// Replaces `SimulationPlugins`, the server runs the simulation.
+/// Expects a `Client` resource, which owns the connection to the server.
+pub struct ClientPlugin;
+
+impl Plugin for ClientPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<PlayerCommand>()
+            .init_resource::<Players>()
+            .init_resource::<LocalPlayer>()
+            .add_system_to_stage(stage::PRE_UPDATE, receive_system.system())
+            .add_system(interpolation_system.system())
+            .add_system_to_stage(stage::POST_UPDATE, send_system.system());
+    }
+}
+
+pub struct Client {
+    socket: Socket,
+    server: SocketAddr,
+    /// Commands the server didn't acknowledge yet, starting with sequence number `first_sequence`
+    unacknowledged: Vec<PlayerCommand>,
+    first_sequence: u64,
+    /// Recent states, the server sends deltas from them
+    states: BTreeMap<u64, Vec<UnitState>>,
+    /// Parts of the snapshots some parts are still missing for, by tick and part index
+    partial_snapshots: BTreeMap<u64, BTreeMap<usize, Snapshot>>,
+    entities: HashMap<NetworkId, Entity>,
+    projectiles: HashMap<NetworkId, Entity>,
+    /// Server tick being shown, between two snapshots
+    render_tick: Option<f64>,
+}
+
+impl Client {
+    pub fn bind(address: SocketAddr, server: SocketAddr) -> io::Result<Client> {
+        Ok(Client {
+            socket: Socket::bind(address)?,
+            server,
+            unacknowledged: vec![],
+            first_sequence: 0,
+            states: BTreeMap::new(),
+            partial_snapshots: BTreeMap::new(),
+            entities: HashMap::new(),
+            projectiles: HashMap::new(),
+            render_tick: None,
+        })
+    }
+
+    fn latest_tick(&self) -> Option<u64> {
+        self.states.keys().next_back().cloned()
+    }
+
+    /// The snapshots all of the parts arrived for
+    fn receive_snapshots(&mut self) -> Vec<Snapshot> {
+        let mut snapshots = vec![];
+
+        for (from, part) in self.socket.receive::<SnapshotPart>() {
+            if from != self.server {
+                continue;
+            }
+
+            let tick = part.snapshot.tick;
+            let parts = self.partial_snapshots.entry(tick).or_default();
+            parts.insert(part.index, part.snapshot);
+
+            if parts.len() == part.count {
+                if let Some(parts) = self.partial_snapshots.remove(&tick) {
+                    snapshots.push(Snapshot::join(
+                        parts.into_iter().map(|(_, part)| part).collect(),
+                    ));
+                }
+            }
+        }
+
+        // Snapshots that lost a part are never complete
+        if let Some(latest_tick) = self.partial_snapshots.keys().next_back().cloned() {
+            self.partial_snapshots = self
+                .partial_snapshots
+                .split_off(&latest_tick.saturating_sub(HISTORY_TICKS));
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.tick);
+
+        snapshots
+    }
+}
+
+/// Positions a unit had in the snapshots, by server tick
+pub struct Interpolation {
+    samples: VecDeque<(u64, Vec2)>,
+}
+
+impl Interpolation {
+    fn new(tick: u64, position: Vec2) -> Self {
+        let mut samples = VecDeque::new();
+        samples.push_back((tick, position));
+        Self { samples }
+    }
+
+    fn push(&mut self, tick: u64, position: Vec2) {
+        self.samples.push_back((tick, position));
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Position on `tick`, between the samples around it
+    fn position_at(&self, tick: f64) -> Vec2 {
+        let (mut previous_tick, mut previous) = self.samples[0];
+
+        for (sample_tick, position) in self.samples.iter().cloned() {
+            if sample_tick as f64 >= tick {
+                if sample_tick == previous_tick {
+                    return position;
+                }
+
+                let t = (tick - previous_tick as f64) / (sample_tick - previous_tick) as f64;
+                return previous + (position - previous) * t as f32;
+            }
+
+            previous_tick = sample_tick;
+            previous = position;
+        }
+
+        previous
+    }
+}
+
+fn send_system(mut client: ResMut<Client>, mut reader: EventReader<PlayerCommand>) {
+    for command in reader.iter() {
+        client.unacknowledged.push(command.clone());
+    }
+
+    // Also lets the server know the client is still there
+    let input = ClientInput {
+        received_tick: client.latest_tick(),
+        first_sequence: client.first_sequence,
+        commands: client.unacknowledged.clone(),
+    };
+    client.socket.send(client.server, &input);
+}
+
+fn receive_system(
+    commands: &mut Commands,
+    mut client: ResMut<Client>,
+    mut local_player: ResMut<LocalPlayer>,
+    mut q: QuerySet<(Query<UnitQuery>, Query<ProjectileQuery>)>,
+) {
+    let client = &mut *client;
+
+    for snapshot in client.receive_snapshots() {
+        let acknowledged = (snapshot.next_sequence.saturating_sub(client.first_sequence) as usize)
+            .min(client.unacknowledged.len());
+        client.unacknowledged.drain(..acknowledged);
+        client.first_sequence += acknowledged as u64;
+
+        // Out of order
+        if client.latest_tick() >= Some(snapshot.tick) {
+            continue;
+        }
+
+        if local_player.0 != snapshot.player {
+            local_player.0 = snapshot.player;
+        }
+
+        let state = match snapshot.base_tick {
+            Some(base_tick) => match client.states.get(&base_tick) {
+                Some(base) => snapshot.apply(base),
+                None => continue,
+            },
+            None => snapshot.apply(&[]),
+        };
+
+        update_units(
+            commands,
+            &mut client.entities,
+            q.q0_mut(),
+            snapshot.tick,
+            &state,
+        );
+        update_projectiles(
+            commands,
+            &mut client.projectiles,
+            q.q1_mut(),
+            snapshot.tick,
+            &snapshot.projectiles,
+        );
+
+        client.states.insert(snapshot.tick, state);
+        client.states = client
+            .states
+            .split_off(&snapshot.tick.saturating_sub(HISTORY_TICKS));
+    }
+}
+
+fn update_units(
+    commands: &mut Commands,
+    entities: &mut HashMap<NetworkId, Entity>,
+    query: &mut Query<UnitQuery>,
+    tick: u64,
+    state: &[UnitState],
+) {
+    for unit_state in state.iter() {
+        let position = unit_state.position.0.to_vec2();
+
+        match entities.get(&unit_state.id) {
+            Some(entity) => {
+                // Spawned by an earlier snapshot this frame otherwise, it is up to date
+                if let Ok((mut unit, mut move_order, mut health, dying, mut interpolation)) =
+                    query.get_mut(*entity)
+                {
+                    *unit = unit_state.unit.clone();
+                    *move_order = unit_state.move_order.clone();
+                    *health = unit_state.health.clone();
+                    interpolation.push(tick, position);
+
+                    if let (None, Some(state_dying)) = (dying, &unit_state.dying) {
+                        commands.insert_one(*entity, state_dying.clone());
+                    }
+                }
+            }
+            None => {
+                commands.spawn((
+                    unit_state.id,
+                    unit_state.owner,
+                    unit_state.unit.clone(),
+                    unit_state.position.clone(),
+                    unit_state.move_order.clone(),
+                    unit_state.health.clone(),
+                    Interpolation::new(tick, position),
+                ));
+                if let Some(dying) = &unit_state.dying {
+                    commands.with(dying.clone());
+                }
+                if let Some(entity) = commands.current_entity() {
+                    entities.insert(unit_state.id, entity);
+                }
+            }
+        }
+    }
+
+    let ids: HashSet<NetworkId> = state.iter().map(|unit| unit.id).collect();
+    despawn_removed(commands, entities, &ids);
+}
+
+fn update_projectiles(
+    commands: &mut Commands,
+    entities: &mut HashMap<NetworkId, Entity>,
+    query: &mut Query<ProjectileQuery>,
+    tick: u64,
+    projectiles: &[ProjectileState],
+) {
+    for projectile_state in projectiles.iter() {
+        let position = projectile_state.position.0.to_vec2();
+
+        match entities.get(&projectile_state.id) {
+            Some(entity) => {
+                if let Ok((mut projectile, mut interpolation)) = query.get_mut(*entity) {
+                    // Homing projectiles change destination
+                    *projectile = projectile_state.projectile.clone();
+                    interpolation.push(tick, position);
+                }
+            }
+            None => {
+                commands.spawn((
+                    projectile_state.id,
+                    projectile_state.projectile.clone(),
+                    projectile_state.position.clone(),
+                    Interpolation::new(tick, position),
+                ));
+                if let Some(entity) = commands.current_entity() {
+                    entities.insert(projectile_state.id, entity);
+                }
+            }
+        }
+    }
+
+    // Landed
+    let ids: HashSet<NetworkId> = projectiles.iter().map(|projectile| projectile.id).collect();
+    despawn_removed(commands, entities, &ids);
+}
+
+fn despawn_removed(
+    commands: &mut Commands,
+    entities: &mut HashMap<NetworkId, Entity>,
+    ids: &HashSet<NetworkId>,
+) {
+    entities.retain(|id, entity| {
+        let kept = ids.contains(id);
+        if !kept {
+            commands.despawn(*entity);
+        }
+        kept
+    });
+}
+
+fn interpolation_system(
+    time: Res<Time>,
+    mut client: ResMut<Client>,
+    mut query: Query<(&Interpolation, &mut Position)>,
+) {
+    let latest_tick = match client.latest_tick() {
+        Some(tick) => tick as f64,
+        None => return,
+    };
+    let target = latest_tick - INTERPOLATION_TICKS as f64;
+
+    let render_tick = match client.render_tick {
+        Some(tick) => tick + time.delta_seconds_f64() * TICKS_PER_SECOND as f64,
+        None => target,
+    };
+
+    // Snapshots don't arrive at a perfectly steady pace, jump when too far behind or ahead
+    let render_tick = if (render_tick - target).abs() > INTERPOLATION_TICKS as f64 {
+        target
+    } else {
+        render_tick.min(latest_tick)
+    };
+    client.render_tick = Some(render_tick);
+
+    for (interpolation, mut position) in query.iter_mut() {
+        position.0 = Vector::from(interpolation.position_at(render_tick));
+    }
+}
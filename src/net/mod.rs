@@ -0,0 +1,424 @@
+mod client;
+mod server;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::combat::{Dying, Health};
+use crate::command::PlayerCommand;
+use crate::player::{Owner, PlayerId};
+use crate::projectile::Projectile;
+use crate::simulation::TICKS_PER_SECOND;
+use crate::unit::{MoveOrder, Position, Unit};
+
+pub use self::client::{Client, ClientPlugin};
+pub use self::server::{run_server, Server, ServerPlugin};
+
+/// The server sends the state of the units every `SNAPSHOT_TICKS` ticks
+pub const SNAPSHOT_TICKS: u64 = 3;
+/// Clients show the units this far behind the latest snapshot, so there is
+/// always a snapshot on each side of what they show, even when one is lost.
+pub const INTERPOLATION_TICKS: u64 = 2 * SNAPSHOT_TICKS;
+/// How long the server and clients keep the states deltas are computed from
+const HISTORY_TICKS: u64 = TICKS_PER_SECOND as u64;
+
+// Biggest UDP datagram
+const MAX_PACKET_SIZE: usize = 64 * 1024;
+/// Snapshots are split in parts that fit in a single ethernet frame,
+/// only a part with a single unit with a long path can be bigger
+const MAX_SNAPSHOT_PART_SIZE: usize = 1200;
+
+/// Identifies a unit on the server and its clients, entities are not the same on each of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct NetworkId(pub u32);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnitState {
+    pub id: NetworkId,
+    pub owner: Owner,
+    pub unit: Unit,
+    pub position: Position,
+    pub move_order: MoveOrder,
+    pub health: Health,
+    pub dying: Option<Dying>,
+}
+
+/// Clients only draw projectiles, their hits come with the units' health
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectileState {
+    pub id: NetworkId,
+    pub projectile: Projectile,
+    pub position: Position,
+}
+
+/// Sent by clients every frame
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ClientInput {
+    /// Latest snapshot received, the next ones are deltas from it
+    received_tick: Option<u64>,
+    /// Sequence number of the first command, commands are sent again
+    /// until the server acknowledges them
+    first_sequence: u64,
+    commands: Vec<PlayerCommand>,
+}
+
+/// State of the units on `tick`, sent by the server to each client
+/// Only has the units that changed since `base_tick` when there is one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Snapshot {
+    tick: u64,
+    /// The player the client plays as
+    player: PlayerId,
+    base_tick: Option<u64>,
+    /// Sequence number of the next command the server expects from the client
+    next_sequence: u64,
+    units: Vec<UnitState>,
+    removed: Vec<NetworkId>,
+    /// Every projectile in flight, they move every tick so they're never sent as deltas
+    projectiles: Vec<ProjectileState>,
+}
+
+impl Snapshot {
+    /// Snapshot of `state`, a delta from `base` when there is one
+    fn new(
+        tick: u64,
+        player: PlayerId,
+        base: Option<(u64, &[UnitState])>,
+        state: &[UnitState],
+        projectiles: &[ProjectileState],
+        next_sequence: u64,
+    ) -> Snapshot {
+        let (base_tick, base) = match base {
+            Some((base_tick, base)) => (Some(base_tick), by_id(base)),
+            None => (None, BTreeMap::new()),
+        };
+        let current = by_id(state);
+
+        Snapshot {
+            tick,
+            player,
+            base_tick,
+            next_sequence,
+            units: state
+                .iter()
+                .filter(|unit| base.get(&unit.id) != Some(unit))
+                .cloned()
+                .collect(),
+            removed: base
+                .keys()
+                .filter(|id| !current.contains_key(id))
+                .cloned()
+                .collect(),
+            projectiles: projectiles.to_vec(),
+        }
+    }
+
+    /// Full state on `tick`, from the state on `base_tick`
+    fn apply(&self, base: &[UnitState]) -> Vec<UnitState> {
+        let mut state: BTreeMap<NetworkId, UnitState> =
+            base.iter().map(|unit| (unit.id, unit.clone())).collect();
+
+        for id in self.removed.iter() {
+            state.remove(id);
+        }
+        for unit in self.units.iter() {
+            state.insert(unit.id, unit.clone());
+        }
+
+        state.into_iter().map(|(_, unit)| unit).collect()
+    }
+
+    /// Split in as few parts of at most `MAX_SNAPSHOT_PART_SIZE` bytes as it takes
+    fn split(&self) -> Vec<SnapshotPart> {
+        let most_items = self
+            .units
+            .len()
+            .max(self.removed.len())
+            .max(self.projectiles.len());
+        let mut count = 1;
+
+        loop {
+            let parts = self.parts(count);
+            let fits = parts.iter().all(|part| {
+                serde_json::to_vec(part)
+                    .map_or(false, |packet| packet.len() <= MAX_SNAPSHOT_PART_SIZE)
+            });
+
+            if fits || count >= most_items {
+                return parts;
+            }
+            count *= 2;
+        }
+    }
+
+    fn parts(&self, count: usize) -> Vec<SnapshotPart> {
+        (0..count)
+            .map(|index| SnapshotPart {
+                index,
+                count,
+                snapshot: Snapshot {
+                    tick: self.tick,
+                    player: self.player,
+                    base_tick: self.base_tick,
+                    next_sequence: self.next_sequence,
+                    units: nth_chunk(&self.units, index, count),
+                    removed: nth_chunk(&self.removed, index, count),
+                    projectiles: nth_chunk(&self.projectiles, index, count),
+                },
+            })
+            .collect()
+    }
+
+    /// The snapshot `parts` were split from, in the order of their `index`
+    fn join(parts: Vec<Snapshot>) -> Snapshot {
+        let mut parts = parts.into_iter();
+        let mut snapshot = parts.next().expect("Snapshot without parts");
+
+        for part in parts {
+            snapshot.units.extend(part.units);
+            snapshot.removed.extend(part.removed);
+            snapshot.projectiles.extend(part.projectiles);
+        }
+
+        snapshot
+    }
+}
+
+/// One packet of a snapshot, with some of its units and projectiles
+/// A snapshot is only used once all of its parts arrived.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SnapshotPart {
+    index: usize,
+    count: usize,
+    snapshot: Snapshot,
+}
+
+/// Part `index` of `items` cut in `count` parts of the same size, the last ones can be smaller
+fn nth_chunk<T: Clone>(items: &[T], index: usize, count: usize) -> Vec<T> {
+    let size = (items.len() + count - 1) / count;
+    items
+        .iter()
+        .skip(index * size)
+        .take(size)
+        .cloned()
+        .collect()
+}
+
+fn by_id(state: &[UnitState]) -> BTreeMap<NetworkId, &UnitState> {
+    state.iter().map(|unit| (unit.id, unit)).collect()
+}
+
+/// Non blocking UDP socket exchanging JSON messages
+struct Socket(UdpSocket);
+
+impl Socket {
+    fn bind(address: SocketAddr) -> io::Result<Socket> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Socket(socket))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+
+    fn send<T: Serialize>(&self, to: SocketAddr, message: &T) {
+        let packet = serde_json::to_vec(message).expect("Failed to serialize network message");
+
+        // Lost packets are made up for by the next ones
+        if let Err(e) = self.0.send_to(&packet, to) {
+            warn!("Failed to send packet to {}: {}", to, e);
+        }
+    }
+
+    fn receive<T: DeserializeOwned>(&self) -> Vec<(SocketAddr, T)> {
+        let mut messages = vec![];
+        let mut buffer = vec![0; MAX_PACKET_SIZE];
+
+        loop {
+            match self.0.recv_from(&mut buffer) {
+                Ok((size, from)) => match serde_json::from_slice(&buffer[..size]) {
+                    Ok(message) => messages.push((from, message)),
+                    Err(e) => warn!("Ignoring invalid message from {}: {}", from, e),
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Failed to receive packet: {}", e);
+                    break;
+                }
+            }
+        }
+
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::core::CorePlugin;
+
+    use super::*;
+    use crate::combat::TargetFilter;
+    use crate::math::{Real, Scalar, Vector};
+    use crate::projectile::ProjectileKind;
+    use crate::test_harness::TestApp;
+    use crate::unit::Facing;
+
+    fn localhost() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    fn unit_state(id: u32, x: f32) -> UnitState {
+        UnitState {
+            id: NetworkId(id),
+            owner: Owner(PlayerId(0)),
+            unit: Unit {
+                archetype: "dino".to_string(),
+                selected: false,
+                velocity: Vector::zero(),
+                max_speed: Scalar::from_int(100),
+                max_force: Scalar::from_int(250),
+                size: Vector::new(Scalar::from_int(16), Scalar::from_int(24)),
+                facing: Facing::Right,
+            },
+            position: Position(Vector::from(Vec2::new(x, 0.0))),
+            move_order: MoveOrder { path: vec![] },
+            health: Health::new(100),
+            dying: None,
+        }
+    }
+
+    fn positions_by_id(app: &App) -> Vec<Vec2> {
+        let mut units: Vec<_> = app
+            .world
+            .query_filtered::<(&NetworkId, &Position), With<Unit>>()
+            .map(|(id, position)| (*id, position.0.to_vec2()))
+            .collect();
+        units.sort_by_key(|(id, _)| *id);
+        units.into_iter().map(|(_, position)| position).collect()
+    }
+
+    #[test]
+    fn test_delta_snapshot() {
+        let base = vec![unit_state(0, 0.0), unit_state(1, 0.0), unit_state(2, 0.0)];
+        let state = vec![unit_state(0, 0.0), unit_state(2, 5.0), unit_state(3, 0.0)];
+
+        let snapshot = Snapshot::new(6, PlayerId(0), Some((3, &base[..])), &state, &[], 0);
+
+        assert_eq!(Some(3), snapshot.base_tick);
+        assert_eq!(vec![unit_state(2, 5.0), unit_state(3, 0.0)], snapshot.units);
+        assert_eq!(vec![NetworkId(1)], snapshot.removed);
+        assert_eq!(state, snapshot.apply(&base));
+    }
+
+    #[test]
+    fn test_big_snapshot_is_split() {
+        let state: Vec<UnitState> = (0..200)
+            .map(|id| {
+                let mut unit = unit_state(id, id as f32);
+                unit.move_order.path = vec![Vector::from(Vec2::new(-300.0, -250.0)); 4];
+                unit
+            })
+            .collect();
+        let snapshot = Snapshot::new(6, PlayerId(0), None, &state, &[], 0);
+        assert!(serde_json::to_vec(&snapshot).unwrap().len() > MAX_SNAPSHOT_PART_SIZE);
+
+        let parts = snapshot.split();
+        assert!(parts.len() > 1);
+        for part in parts.iter() {
+            assert_eq!(parts.len(), part.count);
+            assert!(serde_json::to_vec(part).unwrap().len() <= MAX_SNAPSHOT_PART_SIZE);
+        }
+
+        let parts = parts.into_iter().map(|part| part.snapshot).collect();
+        assert_eq!(snapshot, Snapshot::join(parts));
+    }
+
+    #[test]
+    fn test_snapshot_sends_projectiles() {
+        let sent = ProjectileState {
+            id: NetworkId(4),
+            projectile: Projectile {
+                owner: PlayerId(0),
+                kind: ProjectileKind {
+                    speed: Scalar::from_int(180),
+                    homing: true,
+                    apex: Scalar::zero(),
+                    splash_radius: Scalar::zero(),
+                },
+                damage: 8,
+                targets: TargetFilter::Enemies,
+                target: Some(Entity::new(7)),
+                origin: Vector::zero(),
+                destination: Vector::from(Vec2::new(50.0, 0.0)),
+            },
+            position: Position(Vector::from(Vec2::new(10.0, 0.0))),
+        };
+
+        let snapshot = Snapshot::new(6, PlayerId(0), None, &[], &[sent.clone()], 0);
+        let packet = serde_json::to_vec(&snapshot).unwrap();
+        let received: Snapshot = serde_json::from_slice(&packet).unwrap();
+
+        // The target is an entity of the server
+        assert_eq!(None, received.projectiles[0].projectile.target);
+        assert_eq!(sent.position, received.projectiles[0].position);
+        assert_eq!(
+            sent.projectile.destination,
+            received.projectiles[0].projectile.destination
+        );
+    }
+
+    #[test]
+    fn test_client_follows_server() {
+        let mut server = TestApp::build(|app| {
+            app.insert_resource(Server::bind(localhost()).expect("Couldnt bind server"))
+                .add_plugin(ServerPlugin);
+        });
+        let server_address = server
+            .app
+            .resources
+            .get::<Server>()
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let client = Client::bind(localhost(), server_address).expect("Couldnt bind client");
+        let mut builder = App::build();
+        builder
+            .insert_resource(client)
+            .add_plugin(CorePlugin)
+            .add_plugin(ClientPlugin);
+        let mut client = builder.app;
+
+        {
+            let mut player_commands = client.resources.get_mut::<Events<PlayerCommand>>().unwrap();
+            player_commands.send(PlayerCommand::Select {
+                min: Vector::from(Vec2::new(-120.0, -120.0)),
+                max: Vector::from(Vec2::new(-80.0, -80.0)),
+            });
+            player_commands.send(PlayerCommand::Move {
+                target: Vector::from(Vec2::new(-300.0, -250.0)),
+            });
+        }
+
+        for _ in 0..(20 * TICKS_PER_SECOND) {
+            client.update();
+            server.step();
+        }
+        client.update();
+
+        let server_positions = positions_by_id(&server.app);
+        let client_positions = positions_by_id(&client);
+
+        assert_ne!(Vec2::new(-100.0, -100.0), server_positions[2]);
+        assert_eq!(server_positions.len(), client_positions.len());
+        for (server_position, client_position) in server_positions.iter().zip(client_positions) {
+            assert!((*server_position - client_position).length() < 0.5);
+        }
+    }
+}
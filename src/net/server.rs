@@ -0,0 +1,257 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bevy::app::ScheduleRunnerSettings;
+use bevy::prelude::*;
+
+use super::{
+    ClientInput, NetworkId, ProjectileState, Snapshot, Socket, UnitState, HISTORY_TICKS,
+    SNAPSHOT_TICKS,
+};
+use crate::archetype::UnitArchetypes;
+use crate::combat::{Dying, Health};
+use crate::command::CommandQueue;
+use crate::path_finding::grid::Grid;
+use crate::player::{Owner, PlayerId, Players};
+use crate::plugins::SimulationPlugins;
+use crate::projectile::Projectile;
+use crate::simulation::{MapPath, Tick, SIMULATION_STAGE, TICKS_PER_SECOND};
+use crate::tiled;
+use crate::unit::{MoveOrder, Position, Unit};
+
+// Clients are dropped when not heard from for this long
+const CLIENT_TIMEOUT_TICKS: u64 = 5 * TICKS_PER_SECOND as u64;
+
+/// Authoritative server: runs the simulation, applies the commands clients send
+/// and sends them the state of the units and projectiles.
+/// Expects a `Server` resource, which owns the socket clients connect to.
+pub struct ServerPlugin;
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(network_id_system.system())
+            .add_system(receive_system.system())
+            .add_system_to_stage(SIMULATION_STAGE, snapshot_system.system());
+    }
+}
+
+pub struct Server {
+    socket: Socket,
+    clients: HashMap<SocketAddr, ConnectedClient>,
+    /// Recent states, clients get deltas from the last one they received
+    history: BTreeMap<u64, Vec<UnitState>>,
+    next_id: u32,
+}
+
+#[derive(Debug)]
+struct ConnectedClient {
+    player: PlayerId,
+    next_sequence: u64,
+    received_tick: Option<u64>,
+    last_heard_tick: u64,
+}
+
+impl Server {
+    pub fn bind(address: SocketAddr) -> io::Result<Server> {
+        Ok(Server {
+            socket: Socket::bind(address)?,
+            clients: HashMap::new(),
+            history: BTreeMap::new(),
+            next_id: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+/// Runs a dedicated server on `map_path` in real time, without a window or renderer
+pub fn run_server(
+    map_path: &str,
+    server: Server,
+    archetypes: UnitArchetypes,
+    map: tiled::Map,
+    path_finding_grid: Grid,
+) {
+    info!(
+        "Server listening on {}",
+        server.local_addr().expect("Missing server address")
+    );
+
+    App::build()
+        // Nothing to render, no need to run faster than the simulation
+        .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+            1.0 / TICKS_PER_SECOND as f64,
+        )))
+        .insert_resource(MapPath(map_path.to_string()))
+        .insert_resource(path_finding_grid)
+        .insert_resource(map)
+        .insert_resource(archetypes)
+        .insert_resource(server)
+        .add_plugins(MinimalPlugins)
+        .add_plugins(SimulationPlugins)
+        .add_plugin(ServerPlugin)
+        .run();
+}
+
+fn network_id_system(
+    commands: &mut Commands,
+    mut server: ResMut<Server>,
+    units: Query<Entity, (With<Unit>, Without<NetworkId>)>,
+    projectiles: Query<Entity, (With<Projectile>, Without<NetworkId>)>,
+) {
+    for entity in units.iter().chain(projectiles.iter()) {
+        commands.insert_one(entity, NetworkId(server.next_id));
+        server.next_id += 1;
+    }
+}
+
+fn receive_system(
+    tick: Res<Tick>,
+    players: Res<Players>,
+    mut server: ResMut<Server>,
+    mut queue: ResMut<CommandQueue>,
+) {
+    let inputs = server.socket.receive::<ClientInput>();
+
+    for (address, input) in inputs {
+        if !server.clients.contains_key(&address) {
+            // Clients play as the first player nobody plays as yet
+            let player = (0..players.players.len() as u8)
+                .map(PlayerId)
+                .find(|player| {
+                    server
+                        .clients
+                        .values()
+                        .all(|client| client.player != *player)
+                });
+
+            match player {
+                Some(player) => {
+                    info!(
+                        "Client {} connected as {}",
+                        address, players.players[player.0 as usize].name
+                    );
+                    server.clients.insert(
+                        address,
+                        ConnectedClient {
+                            player,
+                            next_sequence: 0,
+                            received_tick: None,
+                            last_heard_tick: tick.0,
+                        },
+                    );
+                }
+                None => {
+                    warn!("Ignoring client {}, the game is full", address);
+                    continue;
+                }
+            }
+        }
+
+        let client = server
+            .clients
+            .get_mut(&address)
+            .expect("Client was just added");
+
+        client.last_heard_tick = tick.0;
+        client.received_tick = client.received_tick.max(input.received_tick);
+
+        if input.first_sequence > client.next_sequence {
+            warn!("Client {} skipped commands", address);
+            continue;
+        }
+
+        // Clients send commands again until they're acknowledged, skip the known ones
+        let known = (client.next_sequence - input.first_sequence) as usize;
+        for command in input.commands.into_iter().skip(known) {
+            queue.schedule(tick.0 + 1, client.player, command);
+            client.next_sequence += 1;
+        }
+    }
+}
+
+fn snapshot_system(
+    tick: Res<Tick>,
+    mut server: ResMut<Server>,
+    query: Query<(
+        &NetworkId,
+        &Owner,
+        &Unit,
+        &Position,
+        &MoveOrder,
+        &Health,
+        Option<&Dying>,
+    )>,
+    projectiles: Query<(&NetworkId, &Projectile, &Position)>,
+) {
+    if tick.0 % SNAPSHOT_TICKS != 0 {
+        return;
+    }
+
+    let mut state: Vec<UnitState> = query
+        .iter()
+        .map(
+            |(id, owner, unit, position, move_order, health, dying)| UnitState {
+                id: *id,
+                owner: *owner,
+                unit: unit.clone(),
+                position: position.clone(),
+                move_order: move_order.clone(),
+                health: health.clone(),
+                dying: dying.cloned(),
+            },
+        )
+        .collect();
+    state.sort_by_key(|unit| unit.id);
+
+    let mut projectiles: Vec<ProjectileState> = projectiles
+        .iter()
+        .map(|(id, projectile, position)| ProjectileState {
+            id: *id,
+            projectile: projectile.clone(),
+            position: position.clone(),
+        })
+        .collect();
+    projectiles.sort_by_key(|projectile| projectile.id);
+
+    let server = &mut *server;
+
+    server.clients.retain(|address, client| {
+        let connected = tick.0.saturating_sub(client.last_heard_tick) < CLIENT_TIMEOUT_TICKS;
+        if !connected {
+            info!("Client {} timed out", address);
+        }
+        connected
+    });
+
+    for (address, client) in server.clients.iter() {
+        // Clients that didn't receive any of the recent states get all of it
+        let base = client.received_tick.and_then(|received_tick| {
+            server
+                .history
+                .get(&received_tick)
+                .map(|base| (received_tick, base.as_slice()))
+        });
+
+        let snapshot = Snapshot::new(
+            tick.0,
+            client.player,
+            base,
+            &state,
+            &projectiles,
+            client.next_sequence,
+        );
+        for part in snapshot.split() {
+            server.socket.send(*address, &part);
+        }
+    }
+
+    server.history.insert(tick.0, state);
+    server.history = server
+        .history
+        .split_off(&tick.0.saturating_sub(HISTORY_TICKS));
+}
@@ -0,0 +1,373 @@
+use bevy::app::stage;
+use bevy::prelude::*;
+
+use crate::combat::{self, AttackOrder, Dying};
+use crate::command::{CommandQueue, CommandScheduling, PlayerCommand, TickCommand};
+use crate::math::Vector;
+use crate::mouse_position::MouseWorldPosition;
+use crate::path_finding;
+use crate::path_finding::grid::Grid;
+use crate::player::{LocalPlayer, Owner, PlayerId, Players, Relation};
+use crate::simulation::{Tick, SIMULATION_STAGE};
+use crate::unit::{AttackMoveOrder, MoveOrder, Position, Unit};
+
+/// Schedules `PlayerCommand` events for the next tick and applies them at the start of it
+pub struct OrderPlugin;
+
+impl Plugin for OrderPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        if !app.resources().contains::<CommandScheduling>() {
+            app.insert_resource(CommandScheduling::NextTick);
+        }
+        if !app.resources().contains::<LocalPlayer>() {
+            app.insert_resource(LocalPlayer::default());
+        }
+
+        app.init_resource::<CommandQueue>()
+            .add_event::<PlayerCommand>()
+            .add_event::<TickCommand>()
+            .add_system_to_stage(stage::POST_UPDATE, schedule_commands_system.system())
+            .add_system_to_stage(SIMULATION_STAGE, command_system.system());
+    }
+}
+
+/// Right click to move the selected units or attack an enemy, S to stop them,
+/// A then left click to attack-move
+pub struct OrderInputPlugin;
+
+impl Plugin for OrderInputPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<AttackMoveCursor>()
+            .add_system(order_system.system())
+            .add_system(stop_key_system.system())
+            .add_system(attack_move_input_system.system());
+    }
+}
+
+/// Armed by pressing A, the next left click attack-moves instead of starting a selection box
+#[derive(Debug, Default)]
+pub struct AttackMoveCursor(pub bool);
+
+/// Whether a unit at `position` is inside the selection box spanning `min` to `max`
+pub fn in_selection_box(position: &Position, min: Vector, max: Vector) -> bool {
+    let position = position.0;
+    position.x > min.x && position.x < max.x && position.y > min.y && position.y < max.y
+}
+
+/// Finds a path to `target` and sends the unit on its way
+pub fn order_move(
+    grid: &Grid,
+    position: &Position,
+    target: Vector,
+    unit: &mut Unit,
+    move_order: &mut MoveOrder,
+) {
+    let mut best_path = path_finding::find_path(position.0.to_vec2(), target.to_vec2(), grid);
+
+    unit.velocity = Vector::zero();
+
+    // We're here already
+    best_path.remove(0);
+
+    move_order.path = best_path
+}
+
+// The simulation might not tick every frame, commands are queued
+// so none are lost waiting for the next tick.
+fn schedule_commands_system(
+    scheduling: Res<CommandScheduling>,
+    local_player: Res<LocalPlayer>,
+    tick: Res<Tick>,
+    mut queue: ResMut<CommandQueue>,
+    mut reader: EventReader<PlayerCommand>,
+) {
+    if *scheduling != CommandScheduling::NextTick {
+        return;
+    }
+
+    for command in reader.iter() {
+        queue.schedule(tick.0 + 1, local_player.0, command.clone());
+    }
+}
+
+fn command_system(
+    tick: Res<Tick>,
+    grid: Res<Grid>,
+    players: Res<Players>,
+    mut queue: ResMut<CommandQueue>,
+    mut tick_commands: ResMut<Events<TickCommand>>,
+    mut query: Query<
+        (
+            Entity,
+            &Position,
+            &Owner,
+            &mut Unit,
+            &mut MoveOrder,
+            Option<&mut AttackOrder>,
+            Option<&mut AttackMoveOrder>,
+        ),
+        Without<Dying>,
+    >,
+) {
+    // Players only control their own units
+    let controls =
+        |player: PlayerId, owner: &Owner| players.relation(player, owner.0) == Relation::Own;
+
+    for (player, command) in queue.take_due(tick.0) {
+        match &command {
+            PlayerCommand::Select { min, max } => {
+                for (_, position, owner, mut unit, ..) in query.iter_mut() {
+                    if controls(player, owner) {
+                        unit.selected = in_selection_box(position, *min, *max);
+                    }
+                }
+            }
+            PlayerCommand::Move { target } | PlayerCommand::AttackMove { target } => {
+                let attack_move = match command {
+                    PlayerCommand::AttackMove { .. } => Some(*target),
+                    _ => None,
+                };
+
+                for (_, position, owner, mut unit, mut move_order, order, attack_move_order) in
+                    query.iter_mut()
+                {
+                    if unit.selected && controls(player, owner) {
+                        if let Some(mut order) = order {
+                            order.target = None;
+                        }
+                        if let Some(mut attack_move_order) = attack_move_order {
+                            attack_move_order.target = attack_move;
+                        }
+                        order_move(&grid, position, *target, &mut unit, &mut move_order);
+                    }
+                }
+            }
+            PlayerCommand::Stop => {
+                for (_, _, owner, mut unit, mut move_order, order, attack_move_order) in
+                    query.iter_mut()
+                {
+                    if unit.selected && controls(player, owner) {
+                        if let Some(mut order) = order {
+                            order.target = None;
+                        }
+                        if let Some(mut attack_move_order) = attack_move_order {
+                            attack_move_order.target = None;
+                        }
+                        unit.velocity = Vector::zero();
+                        move_order.path.clear();
+                    }
+                }
+            }
+            PlayerCommand::Attack { target } => {
+                let units: Vec<(Entity, Owner, Position)> = query
+                    .iter_mut()
+                    .map(|(entity, position, owner, ..)| (entity, *owner, position.clone()))
+                    .collect();
+                let enemy = combat::unit_at(
+                    *target,
+                    units
+                        .iter()
+                        .map(|(entity, owner, position)| (*entity, owner, position)),
+                    |owner| players.relation(player, owner.0) == Relation::Enemy,
+                );
+
+                if let Some(enemy) = enemy {
+                    for (_, _, owner, unit, _, order, attack_move_order) in query.iter_mut() {
+                        if !unit.selected || !controls(player, owner) {
+                            continue;
+                        }
+                        if let Some(mut order) = order {
+                            order.target = Some(enemy);
+                        }
+                        if let Some(mut attack_move_order) = attack_move_order {
+                            attack_move_order.target = None;
+                        }
+                    }
+                }
+            }
+        }
+
+        tick_commands.send(TickCommand {
+            tick: tick.0,
+            player,
+            command,
+        });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn order_system(
+    commands: &mut Commands,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mouse_position: Res<MouseWorldPosition>,
+    grid: Res<Grid>,
+    mut player_commands: ResMut<Events<PlayerCommand>>,
+    local_player: Res<LocalPlayer>,
+    players: Res<Players>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    query: Query<(Entity, &Position, &Owner, &Unit), Without<Dying>>,
+) {
+    if mouse_buttons.just_pressed(MouseButton::Right) {
+        let target = Vec2::from(mouse_position.0);
+
+        // Right clicking an enemy attacks it
+        let enemy = combat::unit_at(
+            Vector::from(target),
+            query
+                .iter()
+                .map(|(entity, position, owner, _)| (entity, owner, position)),
+            |owner| players.relation(local_player.0, owner.0) == Relation::Enemy,
+        );
+        if enemy.is_some() {
+            player_commands.send(PlayerCommand::Attack {
+                target: Vector::from(target),
+            });
+            return;
+        }
+
+        player_commands.send(PlayerCommand::Move {
+            target: Vector::from(target),
+        });
+
+        for (_, position, owner, unit) in query.iter() {
+            if unit.selected && owner.0 == local_player.0 {
+                let blue = materials.add(Color::rgba(0.0, 0.0, 255.0, 0.2).into());
+                let red = materials.add(Color::rgba(255.0, 0.0, 0.0, 0.2).into());
+                let black = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.2).into());
+
+                debug_path(commands, position, target, &grid, blue, red, black);
+            }
+        }
+    }
+}
+
+fn stop_key_system(keys: Res<Input<KeyCode>>, mut player_commands: ResMut<Events<PlayerCommand>>) {
+    if keys.just_pressed(KeyCode::S) {
+        player_commands.send(PlayerCommand::Stop);
+    }
+}
+
+fn attack_move_input_system(
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mouse_position: Res<MouseWorldPosition>,
+    mut cursor: ResMut<AttackMoveCursor>,
+    mut player_commands: ResMut<Events<PlayerCommand>>,
+) {
+    if keys.just_pressed(KeyCode::A) {
+        cursor.0 = true;
+    }
+
+    if !cursor.0 {
+        return;
+    }
+
+    if mouse_buttons.just_pressed(MouseButton::Left) {
+        player_commands.send(PlayerCommand::AttackMove {
+            target: Vector::from(Vec2::from(mouse_position.0)),
+        });
+    }
+
+    // Disarmed once the click is over, so the selection box doesn't start on it
+    if mouse_buttons.just_released(MouseButton::Left) || keys.just_pressed(KeyCode::Escape) {
+        cursor.0 = false;
+    }
+}
+
+fn debug_path(
+    commands: &mut Commands,
+    position: &Position,
+    target: Vec2,
+    grid: &Grid,
+    blue: Handle<ColorMaterial>,
+    red: Handle<ColorMaterial>,
+    black: Handle<ColorMaterial>,
+) {
+    let start = position.0.to_vec2();
+
+    let astar_path = path_finding::astar(start, target, &grid);
+
+    path_finding::draw_astar_path(astar_path, commands, blue);
+
+    let portals = path_finding::funnel_portals(start, target, &grid);
+    path_finding::draw_funnel_portals(portals, commands, red);
+
+    let funnel_path = path_finding::find_path(start, target, &grid);
+    path_finding::draw_funnel_path(funnel_path, commands, black);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_harness::TestApp;
+
+    #[test]
+    fn test_selected_unit_moves_to_right_click() {
+        let mut app = TestApp::new();
+        let start = app.unit_positions();
+
+        // Only the dino spawned at (-100, -100)
+        app.drag_select(Vec2::new(-120.0, -120.0), Vec2::new(-80.0, -80.0));
+
+        let target = Vec2::new(-300.0, -250.0);
+        app.right_click(target);
+        app.step_seconds(15.0);
+
+        let end = app.unit_positions();
+        assert!(
+            (end[2] - target).length() < 16.0,
+            "Unit stopped at {}",
+            end[2]
+        );
+
+        // The others were not selected and stayed in place
+        assert_eq!(start[0], end[0]);
+        assert_eq!(start[1], end[1]);
+        assert_eq!(start[3], end[3]);
+    }
+
+    #[test]
+    fn test_stop_command() {
+        let mut app = TestApp::new();
+
+        app.send_command(PlayerCommand::Select {
+            min: Vector::from(Vec2::new(-120.0, -120.0)),
+            max: Vector::from(Vec2::new(-80.0, -80.0)),
+        });
+        app.send_command(PlayerCommand::Move {
+            target: Vector::from(Vec2::new(-300.0, -250.0)),
+        });
+        app.step_seconds(1.0);
+
+        app.send_command(PlayerCommand::Stop);
+        app.step_seconds(0.1);
+        let stopped_at = app.unit_positions()[2];
+        assert_ne!(Vec2::new(-100.0, -100.0), stopped_at);
+
+        app.step_seconds(1.0);
+        assert_eq!(stopped_at, app.unit_positions()[2]);
+    }
+
+    #[test]
+    fn test_only_own_units_take_orders() {
+        let mut app = TestApp::new();
+        let start = app.unit_positions();
+
+        // Around the dinos at (100, 100), owned by the other player, and (0, 0)
+        app.drag_select(Vec2::new(-20.0, -20.0), Vec2::new(120.0, 120.0));
+        app.right_click(Vec2::new(-300.0, -250.0));
+        app.step_seconds(1.0);
+
+        let end = app.unit_positions();
+        assert_eq!(start[0], end[0]);
+        assert_ne!(start[1], end[1]);
+
+        let selected: Vec<bool> = app
+            .app
+            .world
+            .query::<&Unit>()
+            .map(|unit| unit.selected)
+            .collect();
+        assert_eq!(vec![false, true, false, false], selected);
+    }
+}
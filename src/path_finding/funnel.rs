@@ -1,5 +1,7 @@
 use bevy::prelude::*;
 
+use crate::math::{Real, Scalar, Vector};
+
 type Location = (i32, i32);
 
 /// A portal is kind of a door, it has a left and right position
@@ -198,9 +200,10 @@ impl Funnel {
     /// Returns the optimal path across the grid running the funnel / string pulling algorithm
     /// See Simple Stupid Funnel Algorithm: https://digestingduck.blogspot.com/2010/03/simple-stupid-funnel-algorithm.html
     /// See Paper: https://www.aaai.org/Papers/AAAI/2006/AAAI06-148.pdf
-    pub fn string_pull(&self) -> Vec<Vec2> {
-        let mut points: Vec<Vec2> = vec![];
-        points.push(self.start);
+    /// Runs on simulation `Scalar`s so the resulting path is the same on every machine
+    pub fn string_pull(&self) -> Vec<Vector> {
+        let mut points: Vec<Vector> = vec![];
+        points.push(Vector::from(self.start));
 
         // Setup our initial search state
         let first_portal = self.portals.get(0);
@@ -208,9 +211,9 @@ impl Funnel {
             return points;
         }
 
-        let mut apex = self.start;
-        let mut portal_left = first_portal.unwrap().left;
-        let mut portal_right = first_portal.unwrap().right;
+        let mut apex = Vector::from(self.start);
+        let mut portal_left = Vector::from(first_portal.unwrap().left);
+        let mut portal_right = Vector::from(first_portal.unwrap().right);
 
         let mut left_index = 0;
         let mut right_index = 0;
@@ -218,59 +221,51 @@ impl Funnel {
 
         while i < self.portals.len() {
             let portal = self.portals.get(i).unwrap();
-
-            println!(
-                "Apex = {} Current Portal R = {} Next Portal R = {}",
-                apex, portal_right, portal.right
-            );
-            println!(
-                "Apex = {} Current Portal L = {} Next Portal L = {}",
-                apex, portal_left, portal.left
-            );
+            let next_left = Vector::from(portal.left);
+            let next_right = Vector::from(portal.right);
 
             // Start with updating the right vertex
             // Don't update if we're outside the funnel
-            if Funnel::cross_product_magnitude_2d(apex, portal_right, portal.right) <= 0.0 {
+            if Funnel::cross_product_magnitude_2d(apex, portal_right, next_right) <= Scalar::zero()
+            {
                 if apex == portal_right
-                    || Funnel::cross_product_magnitude_2d(apex, portal_left, portal.right) > 0.0
+                    || Funnel::cross_product_magnitude_2d(apex, portal_left, next_right)
+                        > Scalar::zero()
                 {
                     // No crossing, we can go to the next portal.
-                    portal_right = portal.right;
+                    portal_right = next_right;
                     right_index = i;
-                    println!("Advancing Right Portal");
                 } else {
                     // If we crossed the left portal, we found a point
                     points.push(portal_left);
                     apex = portal_left;
                     right_index = left_index;
                     i = left_index;
-                    println!("Right Crossed Left: {} is now apex", portal_left);
                 }
             }
 
             // Now update the left vertex
             // Don't update if we're outside the funnel
-            if Funnel::cross_product_magnitude_2d(apex, portal_left, portal.left) >= 0.0 {
+            if Funnel::cross_product_magnitude_2d(apex, portal_left, next_left) >= Scalar::zero() {
                 // If we crossed the left portal, we found a point
                 if apex == portal_left
-                    || Funnel::cross_product_magnitude_2d(apex, portal_right, portal.left) < 0.0
+                    || Funnel::cross_product_magnitude_2d(apex, portal_right, next_left)
+                        < Scalar::zero()
                 {
-                    portal_left = portal.left;
+                    portal_left = next_left;
                     left_index = i;
-                    println!("Advancing Left Portal")
                 } else {
                     points.push(portal_right);
                     apex = portal_right;
                     left_index = right_index;
                     i = right_index;
-                    println!("Left Crossed Right: {} is now apex", portal_right);
                 }
             }
 
             i += 1;
         }
 
-        points.push(self.end);
+        points.push(Vector::from(self.end));
 
         points
     }
@@ -279,7 +274,7 @@ impl Funnel {
     // In the "right-handed" coordinate system, if the result is 0, the points are collinear;
     // if it is positive, the three points constitute a positive angle of rotation around p 1 from p 2 to p 3,
     // otherwise a negative angle. From another point of view, the sign of P whether p 3 lies to the left or to the right of line p1, p2.
-    pub fn cross_product_magnitude_2d(apex: Vec2, left: Vec2, right: Vec2) -> f32 {
+    pub fn cross_product_magnitude_2d(apex: Vector, left: Vector, right: Vector) -> Scalar {
         let a = left - apex;
         let b = right - apex;
         b.x * a.y - a.x * b.y
@@ -362,10 +357,10 @@ mod tests {
             funnel.portals
         );
 
-        let expected: Vec<Vec2> = vec![
-            Vec2::new(48.0, 48.0),
-            Vec2::new(32.0, 32.0),
-            Vec2::new(80.0, 16.0),
+        let expected: Vec<Vector> = vec![
+            Vector::from(Vec2::new(48.0, 48.0)),
+            Vector::from(Vec2::new(32.0, 32.0)),
+            Vector::from(Vec2::new(80.0, 16.0)),
         ];
         assert_eq!(expected, funnel.string_pull());
     }
@@ -387,11 +382,11 @@ mod tests {
             320.0,
         );
 
-        let expected: Vec<Vec2> = vec![
-            Vec2::zero(),
-            Vec2::new(32.0, 64.0),
-            Vec2::new(48.0, 112.0),
-            Vec2::new(48.0, 192.0),
+        let expected: Vec<Vector> = vec![
+            Vector::zero(),
+            Vector::from(Vec2::new(32.0, 64.0)),
+            Vector::from(Vec2::new(48.0, 112.0)),
+            Vector::from(Vec2::new(48.0, 192.0)),
         ];
         assert_eq!(expected, funnel.string_pull());
     }
@@ -403,25 +398,25 @@ mod tests {
         // |   r
         // |o_________
         let magnitude = Funnel::cross_product_magnitude_2d(
-            Vec2::zero(),
-            Vec2::new(3.0, 5.0),
-            Vec2::new(3.0, 2.0),
+            Vector::zero(),
+            Vector::from(Vec2::new(3.0, 5.0)),
+            Vector::from(Vec2::new(3.0, 2.0)),
         );
 
         // Positive angle
-        assert_eq!(9.0, magnitude);
+        assert_eq!(Scalar::from_int(9), magnitude);
 
         // |
         // |   r
         // |   l
         // |o_________
         let magnitude = Funnel::cross_product_magnitude_2d(
-            Vec2::zero(),
-            Vec2::new(3.0, 2.0),
-            Vec2::new(3.0, 5.0),
+            Vector::zero(),
+            Vector::from(Vec2::new(3.0, 2.0)),
+            Vector::from(Vec2::new(3.0, 5.0)),
         );
 
         // Negative because right crossed left
-        assert_eq!(-9.0, magnitude);
+        assert_eq!(Scalar::from_int(-9), magnitude);
     }
 }
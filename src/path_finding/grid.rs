@@ -1,14 +1,18 @@
-use crate::tiled::{Map, PropertyValue};
+use serde::{Deserialize, Serialize};
+
+use crate::math::{Real, Scalar, Vector};
+use crate::tiled::{CustomProperties, Map};
 
 const WALKABLE: &str = "walkable";
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TileType {
     WALKABLE,
     UNWALKABLE,
 }
 
-#[derive(Debug)]
+/// Empty by default, until the map is loaded
+#[derive(Debug, Default, Clone)]
 pub struct Grid {
     grid: Vec<Vec<TileType>>,
     pub tile_size: f32,
@@ -20,45 +24,32 @@ pub struct GridError;
 impl Grid {
     /// Builds a collision grid for path finding from a Tiled map
     pub fn from_tiled_map(map: &Map) -> Result<Grid, GridError> {
+        // Infinite maps are cut down to the tiles that were painted
+        let bounds = map.bounds();
         let mut grid = Grid {
-            grid: vec![],
+            grid: vec![vec![TileType::WALKABLE; bounds.width as usize]; bounds.height as usize],
             tile_size: map.tile_width as f32,
         };
 
-        for y in 0..map.height {
-            let mut current_row = vec![];
-
-            for x in 0..map.width {
-                let mut tile_type = TileType::WALKABLE;
-
-                for layer in map.layers.iter() {
+        for (_, layer) in map.tile_layers() {
+            for chunk in layer.chunks.iter() {
+                for (x, y, tile) in chunk.tiles() {
                     // Check for a collision on each layer
                     // Collisions are defined as custom properties on the tiles
-                    let tile_number = layer.data[(y * layer.width + x) as usize];
-                    // Assuming a single tileset at the moment
-                    let tileset = &map.tilesets[0];
-
-                    // Indexing is weird?
-                    let tile = tileset.tiles.iter().find(|&t| t.id == tile_number - 1);
+                    // Flipped tiles keep their properties
+                    let tile = map
+                        .resolve_gid(tile.gid)
+                        .and_then(|(tileset, id)| map.tilesets[tileset].tile(id));
 
                     if let Some(tile) = tile {
-                        let walkable_property =
-                            tile.properties.iter().find(|&p| p.name == WALKABLE);
-
-                        if let Some(walk) = walkable_property {
-                            if let PropertyValue::Bool(w) = walk.value {
-                                if !w {
-                                    tile_type = TileType::UNWALKABLE;
-                                }
-                            }
+                        if tile.property_bool(WALKABLE) == Some(false) {
+                            // Rows go bottom up
+                            let row = bounds.height - 1 - (y - bounds.y);
+                            grid.set((x - bounds.x, row), TileType::UNWALKABLE);
                         }
                     }
                 }
-
-                current_row.push(tile_type);
             }
-
-            grid.grid.insert(0, current_row);
         }
 
         Ok(grid)
@@ -68,6 +59,25 @@ impl Grid {
         self.grid[position.1 as usize][position.0 as usize]
     }
 
+    pub fn set(&mut self, position: (i32, i32), tile_type: TileType) {
+        self.grid[position.1 as usize][position.0 as usize] = tile_type;
+    }
+
+    /// Tiles of this grid that are different in `other`, with their type in `other`
+    pub fn changes(&self, other: &Grid) -> Vec<((i32, i32), TileType)> {
+        let mut changes = vec![];
+
+        for (y, (row, other_row)) in self.grid.iter().zip(other.grid.iter()).enumerate() {
+            for (x, (tile, other_tile)) in row.iter().zip(other_row.iter()).enumerate() {
+                if tile != other_tile {
+                    changes.push(((x as i32, y as i32), *other_tile));
+                }
+            }
+        }
+
+        changes
+    }
+
     pub fn accessible_neighbors(&self, position: (i32, i32)) -> Vec<(i32, i32)> {
         let neighbor_deltas = vec![
             (0, -1),
@@ -116,6 +126,37 @@ impl Grid {
         result
     }
 
+    /// Tile under the world `position`, None outside of the map
+    pub fn tile_at(&self, position: Vector) -> Option<(i32, i32)> {
+        let x = ((position.x.to_f32() + self.map_width() / 2.0) / self.tile_size).floor() as i32;
+        let y = ((position.y.to_f32() + self.map_height() / 2.0) / self.tile_size).floor() as i32;
+
+        let inbounds =
+            x >= 0 && y >= 0 && y < self.grid.len() as i32 && x < self.grid[0].len() as i32;
+        if inbounds {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+
+    /// First point on the way from `from` to `to` that is in an unwalkable tile or off the map,
+    /// None when there is a line of sight
+    pub fn raycast(&self, from: Vector, to: Vector) -> Option<Vector> {
+        // A few samples per tile, so corners are not skipped over
+        let sample_distance = self.tile_size / 4.0;
+        let steps = ((to - from).length().to_f32() / sample_distance)
+            .ceil()
+            .max(1.0) as i32;
+
+        (1..=steps)
+            .map(|step| from + (to - from) * Scalar::from_int(step) / Scalar::from_int(steps))
+            .find(|point| match self.tile_at(*point) {
+                Some(tile) => self.at(tile) == TileType::UNWALKABLE,
+                None => true,
+            })
+    }
+
     pub fn map_width(&self) -> f32 {
         if self.grid.first().is_some() {
             self.grid.first().unwrap().len() as f32 * self.tile_size
@@ -131,15 +172,57 @@ impl Grid {
 
 #[cfg(test)]
 mod tests {
+    use bevy::prelude::*;
+
     use super::*;
 
     #[test]
     fn test_from_tiled_map() {
-        let map = Map::from_json_file("assets/basic_map.json").expect("Failed to load map");
+        let map = Map::from_file("assets/basic_map.tmj").expect("Failed to load map");
 
         let grid = Grid::from_tiled_map(&map).expect("Failed to build grid from Tiled map");
 
         let tile_walk_type = grid.at((0, 0));
         assert_eq!(TileType::WALKABLE, tile_walk_type);
     }
+
+    #[test]
+    fn test_infinite_map() {
+        let map = Map::from_file("assets/test_maps/infinite.json").expect("Failed to load map");
+        let grid = Grid::from_tiled_map(&map).expect("Failed to build grid from Tiled map");
+
+        assert_eq!(8.0 * 32.0, grid.map_width());
+        // The top left and bottom right painted tiles aren't walkable
+        assert_eq!(TileType::UNWALKABLE, grid.at((0, 7)));
+        assert_eq!(TileType::UNWALKABLE, grid.at((7, 0)));
+        assert_eq!(TileType::WALKABLE, grid.at((3, 1)));
+
+        let top_left = map.world_position(-112.0, -112.0);
+        assert_eq!(Some((0, 7)), grid.tile_at(Vector::from(top_left)));
+    }
+
+    #[test]
+    fn test_raycast() {
+        let map = Map::from_file("assets/basic_map.tmj").expect("Failed to load map");
+        let mut grid = Grid::from_tiled_map(&map).expect("Failed to build grid from Tiled map");
+
+        let from = Vector::from(Vec2::new(-100.0, 10.0));
+        let to = Vector::from(Vec2::new(100.0, 10.0));
+        assert_eq!(None, grid.raycast(from, to));
+
+        // Block the tile right of the origin
+        let wall = grid.tile_at(Vector::from(Vec2::new(40.0, 10.0))).unwrap();
+        grid.set(wall, TileType::UNWALKABLE);
+
+        let hit = grid.raycast(from, to).expect("Missing hit").to_vec2();
+        assert_eq!(Some(wall), grid.tile_at(Vector::from(hit)));
+        assert!(hit.x < 40.0, "Hit at {}", hit);
+        assert_eq!(
+            None,
+            grid.raycast(to, Vector::from(Vec2::new(100.0, 200.0)))
+        );
+
+        let outside = Vector::from(Vec2::new(0.0, grid.map_height()));
+        assert!(grid.raycast(from, outside).is_some());
+    }
 }
@@ -5,6 +5,7 @@ pub mod grid;
 
 use bevy::prelude::*;
 
+use crate::math::Vector;
 use crate::path_finding::funnel::Funnel;
 use crate::path_finding::grid::Grid;
 use crate::path_finding::path_finder::PathFinder;
@@ -32,7 +33,7 @@ pub fn funnel_portals(start: Vec2, end: Vec2, grid: &Grid) -> Vec<Portal> {
     funnel.portals
 }
 
-pub fn find_path(start: Vec2, end: Vec2, grid: &Grid) -> Vec<Vec2> {
+pub fn find_path(start: Vec2, end: Vec2, grid: &Grid) -> Vec<Vector> {
     // Step 1: Run Astar on the Grid for a global best path
     let path_finder = PathFinder { grid };
     let path = path_finder.path(start, end);
@@ -109,10 +110,10 @@ pub fn draw_funnel_portals(
     }
 }
 
-pub fn draw_funnel_path(path: Vec<Vec2>, commands: &mut Commands, color: Handle<ColorMaterial>) {
+pub fn draw_funnel_path(path: Vec<Vector>, commands: &mut Commands, color: Handle<ColorMaterial>) {
     for i in 0..path.len() - 1 {
-        let p1 = path[i];
-        let p2 = path[i + 1];
+        let p1 = path[i].to_vec2();
+        let p2 = path[i + 1].to_vec2();
 
         let midpoint = (p1 + p2) / 2.0;
         let diff = p2 - p1;
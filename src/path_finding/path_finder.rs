@@ -4,6 +4,7 @@ use std::collections::HashMap;
 
 use bevy::prelude::*;
 
+use crate::math::{Real, Scalar};
 use crate::path_finding::grid::Grid;
 
 pub struct PathFinder<'a> {
@@ -12,20 +13,23 @@ pub struct PathFinder<'a> {
 
 pub type Location = (i32, i32);
 
+/// Cost of moving to a neighboring tile
+const STEP_COST: i32 = 10;
+
 impl<'a> PathFinder<'a> {
     pub fn path(&self, from: Vec2, to: Vec2) -> Vec<Location> {
         let from_location = self.world_to_grid_coordinates(from);
         let to_location = self.world_to_grid_coordinates(to);
 
         let mut came_from = HashMap::<Location, Location>::new();
-        let mut cost_so_far = HashMap::<Location, i32>::new();
+        let mut cost_so_far = HashMap::<Location, Scalar>::new();
 
         let mut open_list = BinaryHeap::new();
         open_list.push(PathNodePriority {
             loc: from_location,
-            f_score: 0,
+            f_score: Scalar::zero(),
         });
-        cost_so_far.insert(from_location, 0);
+        cost_so_far.insert(from_location, Scalar::zero());
 
         while !open_list.is_empty() {
             let current = open_list.pop().unwrap();
@@ -35,7 +39,7 @@ impl<'a> PathFinder<'a> {
             }
 
             for neighbor_location in self.grid.accessible_neighbors(current.loc) {
-                let new_cost = cost_so_far.get(&current.loc).unwrap() + 10;
+                let new_cost = cost_so_far[&current.loc] + Scalar::from_int(STEP_COST);
                 let neighbhor_cost = cost_so_far.get(&neighbor_location);
 
                 if neighbhor_cost.is_none() || &new_cost < neighbhor_cost.unwrap() {
@@ -80,25 +84,27 @@ impl<'a> PathFinder<'a> {
         path
     }
 
-    fn heuristic(&self, a: Location, b: Location) -> i32 {
+    fn heuristic(&self, a: Location, b: Location) -> Scalar {
         // Chebyshev Distance
         // std::cmp::max(b.0 - a.0, b.1 - a.1)
         //
-        // Euclidean Distance
-
-        let euclidian_dist = (((a.0 - b.1).pow(2) + (a.0 - b.1).pow(2)) as f64).sqrt();
-        euclidian_dist as i32
+        // Euclidean Distance, in `Scalar`s so every machine explores the same tiles
+        Scalar::from_int((a.0 - b.0).pow(2) + (a.1 - b.1).pow(2)).sqrt()
     }
 }
 
 pub struct PathNodePriority {
     pub loc: Location,
-    pub f_score: i32,
+    pub f_score: Scalar,
 }
 
 impl Ord for PathNodePriority {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.f_score.cmp(&other.f_score).reverse()
+        // Costs are never NaN
+        self.f_score
+            .partial_cmp(&other.f_score)
+            .unwrap_or(Ordering::Equal)
+            .reverse()
     }
 }
 
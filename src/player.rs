@@ -0,0 +1,109 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct PlayerId(pub u8);
+
+/// The player a unit belongs to, only they can select it and give it orders
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Owner(pub PlayerId);
+
+/// The player issuing commands from this app
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LocalPlayer(pub PlayerId);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Player {
+    pub name: String,
+    /// Tints the player's units
+    pub color: Color,
+    /// Players on the same team are allies
+    pub team: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Relation {
+    Own,
+    Ally,
+    Enemy,
+}
+
+/// Everyone in the game, indexed by `PlayerId`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Players {
+    pub players: Vec<Player>,
+}
+
+impl Default for Players {
+    fn default() -> Self {
+        Self {
+            players: vec![
+                Player {
+                    name: "Blue".to_string(),
+                    color: Color::rgb(0.3, 0.5, 1.0),
+                    team: 0,
+                },
+                Player {
+                    name: "Red".to_string(),
+                    color: Color::rgb(1.0, 0.35, 0.3),
+                    team: 1,
+                },
+            ],
+        }
+    }
+}
+
+impl Players {
+    pub fn get(&self, id: PlayerId) -> Option<&Player> {
+        self.players.get(id.0 as usize)
+    }
+
+    /// How `player` sees `other`
+    pub fn relation(&self, player: PlayerId, other: PlayerId) -> Relation {
+        if player == other {
+            return Relation::Own;
+        }
+
+        match (self.get(player), self.get(other)) {
+            (Some(player), Some(other)) if player.team == other.team => Relation::Ally,
+            _ => Relation::Enemy,
+        }
+    }
+
+    /// Sprite color of a unit owned by `owner`, lighter when selected
+    pub fn unit_color(&self, owner: PlayerId, selected: bool) -> Color {
+        let color = self.get(owner).map_or(Color::WHITE, |player| player.color);
+
+        if selected {
+            Color::rgb(
+                color.r() + (1.0 - color.r()) * 0.6,
+                color.g() + (1.0 - color.g()) * 0.6,
+                color.b() + (1.0 - color.b()) * 0.6,
+            )
+        } else {
+            color
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relations() {
+        let mut players = Players::default();
+        players.players.push(Player {
+            name: "Green".to_string(),
+            color: Color::GREEN,
+            team: 0,
+        });
+
+        assert_eq!(Relation::Own, players.relation(PlayerId(0), PlayerId(0)));
+        assert_eq!(Relation::Enemy, players.relation(PlayerId(0), PlayerId(1)));
+        assert_eq!(Relation::Ally, players.relation(PlayerId(0), PlayerId(2)));
+        assert_eq!(Relation::Enemy, players.relation(PlayerId(2), PlayerId(1)));
+    }
+}
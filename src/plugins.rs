@@ -0,0 +1,53 @@
+use bevy::app::PluginGroupBuilder;
+use bevy::prelude::*;
+
+use crate::animation::AnimationPlugin;
+use crate::archetype::ArchetypeReloadPlugin;
+use crate::camera::CameraPlugin;
+use crate::combat::CombatPlugin;
+use crate::map_setup::MapSetupPlugin;
+use crate::mouse_position::MousePositionPlugin;
+use crate::movement::MovementPlugin;
+use crate::orders::{OrderInputPlugin, OrderPlugin};
+use crate::projectile::{ProjectilePlugin, ProjectileSpritePlugin};
+use crate::save::{SaveInputPlugin, SavePlugin};
+use crate::selection_box::SelectionBoxPlugin;
+use crate::simulation::SimulationPlugin;
+use crate::unit::{UnitPlugin, UnitSpritePlugin};
+
+/// Everything needed to run the game rules, no window or renderer required
+/// Expects the `Map`, its `MapPath` and its path finding `Grid` as resources. While the
+/// `MapSetupPlugin` loads the map they're empty, and the `SimulationClock` is `loading_map`.
+pub struct SimulationPlugins;
+
+impl PluginGroup for SimulationPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group
+            .add(SimulationPlugin)
+            .add(OrderPlugin)
+            .add(UnitPlugin)
+            .add(MovementPlugin)
+            .add(CombatPlugin)
+            .add(ProjectilePlugin)
+            .add(SavePlugin);
+    }
+}
+
+/// Rendering and player input, on top of `SimulationPlugins` and bevy's `DefaultPlugins`
+pub struct PresentationPlugins;
+
+impl PluginGroup for PresentationPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group
+            .add(CameraPlugin)
+            .add(MapSetupPlugin)
+            .add(MousePositionPlugin)
+            .add(SelectionBoxPlugin)
+            .add(OrderInputPlugin)
+            .add(SaveInputPlugin)
+            .add(UnitSpritePlugin)
+            .add(ArchetypeReloadPlugin)
+            .add(ProjectileSpritePlugin)
+            .add(AnimationPlugin);
+    }
+}
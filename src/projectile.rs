@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{Animation, Animations};
+use crate::combat::{apply_hit, Armor, Dying, Health, TargetFilter};
+use crate::math::{Real, Scalar, Vector};
+use crate::path_finding::grid::Grid;
+use crate::player::{Owner, PlayerId, Players};
+use crate::simulation::{timestep, SIMULATION_STAGE};
+use crate::unit::Position;
+
+// Projectiles without splash still hit the units they land on
+const HIT_RADIUS: i32 = 12;
+
+/// Moves the projectiles fired by ranged attacks and deals their damage on impact
+pub struct ProjectilePlugin;
+
+impl Plugin for ProjectilePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(SIMULATION_STAGE, projectile_system.system());
+    }
+}
+
+/// Gives projectiles their sprite, animated by the `AnimationPlugin`
+pub struct ProjectileSpritePlugin;
+
+impl Plugin for ProjectileSpritePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_startup_system(sprite_setup.system())
+            .add_system(spawn_sprite_system.system())
+            .add_system(transform_system.system());
+    }
+}
+
+/// What an `Attack` fires, its damage is dealt on impact instead of right away
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectileKind {
+    /// Distance travelled per second
+    pub speed: Scalar,
+    /// Homing projectiles follow their target, the others fly to where it stood when fired
+    pub homing: bool,
+    /// Height of the arc of ballistic projectiles, they fly over blocked tiles
+    /// Zero for projectiles flying straight, which blocked tiles stop.
+    pub apex: Scalar,
+    /// Units this close to the impact take the damage too
+    pub splash_radius: Scalar,
+}
+
+/// In flight, fired by a unit of `owner`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Projectile {
+    pub owner: PlayerId,
+    pub kind: ProjectileKind,
+    pub damage: i32,
+    pub targets: TargetFilter,
+    /// Followed by homing projectiles while it's alive, entities are local to each app
+    #[serde(skip)]
+    pub target: Option<Entity>,
+    /// Where it was fired from
+    pub origin: Vector,
+    /// Where it lands, unless a blocked tile is in the way
+    pub destination: Vector,
+}
+
+impl ProjectileKind {
+    pub fn is_ballistic(&self) -> bool {
+        self.apex > Scalar::zero()
+    }
+}
+
+impl Projectile {
+    /// Height above the ground at `position`, highest halfway from `origin` to `destination`
+    pub fn height(&self, position: Vector) -> f32 {
+        let total = (self.destination - self.origin).length().to_f32();
+        if total <= 0.0 {
+            return 0.0;
+        }
+
+        let t = ((position - self.origin).length().to_f32() / total).min(1.0);
+        4.0 * self.kind.apex.to_f32() * t * (1.0 - t)
+    }
+}
+
+struct ProjectileSpriteSheet(Handle<TextureAtlas>);
+
+pub fn spawn_projectile(commands: &mut Commands, projectile: Projectile, position: Vector) {
+    commands.spawn((projectile, Position(position)));
+}
+
+fn projectile_system(
+    commands: &mut Commands,
+    grid: Res<Grid>,
+    players: Res<Players>,
+    mut q: QuerySet<(
+        Query<(Entity, &mut Projectile, &mut Position)>,
+        Query<(Entity, &Owner, &Position), (With<Health>, Without<Dying>)>,
+        Query<(&mut Health, Option<&Armor>)>,
+    )>,
+) {
+    let units: Vec<(Entity, PlayerId, Vector)> = q
+        .q1()
+        .iter()
+        .map(|(entity, owner, position)| (entity, owner.0, position.0))
+        .collect();
+    let positions: HashMap<Entity, Vector> = units
+        .iter()
+        .map(|(entity, _, position)| (*entity, *position))
+        .collect();
+
+    let mut hits: Vec<(Entity, i32)> = vec![];
+
+    for (entity, mut projectile, mut position) in q.q0_mut().iter_mut() {
+        if let Some(target) = projectile.target {
+            match positions.get(&target) {
+                Some(target_position) => projectile.destination = *target_position,
+                // Lands where its target was last seen
+                None => projectile.target = None,
+            }
+        }
+
+        let to_destination = projectile.destination - position.0;
+        let distance = to_destination.length();
+        let step = projectile.kind.speed * timestep();
+        let next = if distance <= step {
+            projectile.destination
+        } else {
+            position.0 + to_destination * step / distance
+        };
+
+        // Ballistic projectiles are above the walls until they land
+        let wall = if projectile.kind.is_ballistic() {
+            None
+        } else {
+            grid.raycast(position.0, next)
+        };
+
+        if let Some(wall) = wall {
+            hits.extend(impact(&projectile, wall, None, &units, &players));
+            commands.despawn(entity);
+        } else if next == projectile.destination {
+            hits.extend(impact(
+                &projectile,
+                next,
+                projectile.target,
+                &units,
+                &players,
+            ));
+            commands.despawn(entity);
+        } else {
+            position.0 = next;
+        }
+    }
+
+    for (target, damage) in hits {
+        if let Ok((mut health, armor)) = q.q2_mut().get_mut(target) {
+            health.current = apply_hit(&health, armor, damage);
+        }
+    }
+}
+
+/// Units hit by `projectile` landing on `point`, `direct` is hit even outside the splash
+fn impact(
+    projectile: &Projectile,
+    point: Vector,
+    direct: Option<Entity>,
+    units: &[(Entity, PlayerId, Vector)],
+    players: &Players,
+) -> Vec<(Entity, i32)> {
+    let hit_radius = Scalar::from_int(HIT_RADIUS);
+    let radius = if projectile.kind.splash_radius > hit_radius {
+        projectile.kind.splash_radius
+    } else {
+        hit_radius
+    };
+
+    units
+        .iter()
+        .filter(|(unit, owner, position)| {
+            Some(*unit) == direct
+                || ((*position - point).length() <= radius
+                    && projectile
+                        .targets
+                        .accepts(players.relation(projectile.owner, *owner)))
+        })
+        .map(|(unit, ..)| (*unit, projectile.damage))
+        .collect()
+}
+
+fn sprite_setup(
+    commands: &mut Commands,
+    asset_server: Res<AssetServer>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+) {
+    let texture_handle = asset_server.load("projectile.png");
+    let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(8.0, 8.0), 4, 1);
+    let texture_atlas_handle = texture_atlases.add(texture_atlas);
+
+    commands.insert_resource(ProjectileSpriteSheet(texture_atlas_handle));
+}
+
+fn spawn_sprite_system(
+    commands: &mut Commands,
+    sprite_sheet: Res<ProjectileSpriteSheet>,
+    query: Query<(Entity, &Position), Added<Projectile>>,
+) {
+    for (entity, position) in query.iter() {
+        let mut animations = HashMap::<String, Animation>::new();
+        animations.insert("flying".to_string(), Animation::new(vec![0, 1, 2, 3]));
+
+        commands
+            .insert(
+                entity,
+                SpriteSheetBundle {
+                    texture_atlas: sprite_sheet.0.clone(),
+                    transform: Transform {
+                        // Above the units
+                        translation: position.0.to_vec2().extend(600.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .insert_one(entity, Animations::new("flying".to_string(), animations))
+            .insert_one(entity, Timer::from_seconds(0.05, true));
+    }
+}
+
+/// Follows the simulation position, ballistic projectiles are drawn higher mid-flight
+fn transform_system(mut query: Query<(&Projectile, &Position, &mut Transform), Changed<Position>>) {
+    for (projectile, position, mut transform) in query.iter_mut() {
+        let ground = position.0.to_vec2();
+        transform.translation.x = ground.x;
+        transform.translation.y = ground.y + projectile.height(position.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combat::Attack;
+    use crate::command::PlayerCommand;
+    use crate::path_finding::grid::TileType;
+    use crate::test_harness::TestApp;
+
+    fn unit_at(app: &TestApp, at: Vec2) -> Entity {
+        app.app
+            .world
+            .query::<(Entity, &Position)>()
+            .find(|(_, position)| position.0.to_vec2() == at)
+            .map(|(entity, _)| entity)
+            .expect("Missing unit")
+    }
+
+    fn health(app: &TestApp, entity: Entity) -> i32 {
+        app.app.world.get::<Health>(entity).unwrap().current
+    }
+
+    fn projectile_count(app: &TestApp) -> usize {
+        app.app.world.query::<&Projectile>().count()
+    }
+
+    #[test]
+    fn test_ranged_attack_hits_on_impact() {
+        let mut app = TestApp::new();
+
+        let archer = unit_at(&app, Vec2::new(0.0, 0.0));
+        let enemy = unit_at(&app, Vec2::new(23.0, 42.0));
+        let bystander = unit_at(&app, Vec2::new(100.0, 100.0));
+        {
+            let mut attack = app.app.world.get_mut::<Attack>(archer).unwrap();
+            attack.range = Scalar::from_int(200);
+            attack.projectile = Some(ProjectileKind {
+                speed: Scalar::from_int(60),
+                homing: true,
+                apex: Scalar::zero(),
+                splash_radius: Scalar::from_int(8),
+            });
+        }
+
+        app.send_command(PlayerCommand::Select {
+            min: Vector::from(Vec2::new(-20.0, -20.0)),
+            max: Vector::from(Vec2::new(20.0, 20.0)),
+        });
+        app.send_command(PlayerCommand::Attack {
+            target: Vector::from(Vec2::new(23.0, 42.0)),
+        });
+        app.step_seconds(0.2);
+
+        // Still on its way
+        assert_eq!(1, projectile_count(&app));
+        assert_eq!(100, health(&app, enemy));
+
+        app.step_seconds(0.8);
+        assert_eq!(0, projectile_count(&app));
+        assert_eq!(91, health(&app, enemy));
+        // Too far from the impact for the splash
+        assert_eq!(100, health(&app, bystander));
+    }
+
+    fn spawn_over_wall(app: &mut TestApp, apex: Scalar) {
+        {
+            let mut grid = app.app.resources.get_mut::<Grid>().unwrap();
+            let wall = grid.tile_at(Vector::from(Vec2::new(-20.0, 20.0))).unwrap();
+            grid.set(wall, TileType::UNWALKABLE);
+        }
+
+        let start = Vector::from(Vec2::new(-100.0, 0.0));
+        app.app.world.spawn((
+            Projectile {
+                owner: PlayerId(0),
+                kind: ProjectileKind {
+                    speed: Scalar::from_int(200),
+                    homing: false,
+                    apex,
+                    splash_radius: Scalar::zero(),
+                },
+                damage: 10,
+                targets: TargetFilter::Enemies,
+                target: None,
+                origin: start,
+                destination: Vector::from(Vec2::new(23.0, 42.0)),
+            },
+            Position(start),
+        ));
+    }
+
+    #[test]
+    fn test_projectile_stops_at_blocked_tiles() {
+        let mut app = TestApp::new();
+        let enemy = unit_at(&app, Vec2::new(23.0, 42.0));
+
+        spawn_over_wall(&mut app, Scalar::zero());
+        app.step_seconds(1.0);
+
+        assert_eq!(0, projectile_count(&app));
+        assert_eq!(100, health(&app, enemy));
+    }
+
+    #[test]
+    fn test_ballistic_projectile_flies_over_blocked_tiles() {
+        let mut app = TestApp::new();
+        let enemy = unit_at(&app, Vec2::new(23.0, 42.0));
+
+        spawn_over_wall(&mut app, Scalar::from_int(40));
+        app.step_seconds(1.0);
+
+        assert_eq!(0, projectile_count(&app));
+        assert_eq!(91, health(&app, enemy));
+    }
+
+    #[test]
+    fn test_ballistic_height() {
+        let projectile = Projectile {
+            owner: PlayerId(0),
+            kind: ProjectileKind {
+                speed: Scalar::from_int(100),
+                homing: false,
+                apex: Scalar::from_int(40),
+                splash_radius: Scalar::zero(),
+            },
+            damage: 10,
+            targets: TargetFilter::Enemies,
+            target: None,
+            origin: Vector::zero(),
+            destination: Vector::from(Vec2::new(100.0, 0.0)),
+        };
+
+        assert_eq!(0.0, projectile.height(Vector::zero()));
+        let halfway = projectile.height(Vector::from(Vec2::new(50.0, 0.0)));
+        assert!((halfway - 40.0).abs() < 0.01, "{}", halfway);
+        assert_eq!(0.0, projectile.height(projectile.destination));
+    }
+}
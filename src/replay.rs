@@ -0,0 +1,274 @@
+use std::fmt;
+
+use bevy::app::stage;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::archetype::UnitArchetypes;
+use crate::command::{CommandQueue, TickCommand};
+use crate::player::Players;
+use crate::projectile::Projectile;
+use crate::simulation::{SimulationClock, Tick, TICKS_PER_SECOND};
+use crate::tiled::Map;
+use crate::unit::{self, Unit};
+
+pub const REPLAY_VERSION: u32 = 2;
+
+const SEEK_SECONDS: u64 = 10;
+const MIN_SPEED: f64 = 0.25;
+const MAX_SPEED: f64 = 16.0;
+
+/// Every command issued during a game, replaying them against the same map
+/// in the same build gives back the same game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub map: String,
+    pub commands: Vec<TickCommand>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "replay io error: {}", e),
+            ReplayError::Json(e) => write!(f, "invalid replay: {}", e),
+            ReplayError::UnsupportedVersion(v) => write!(f, "unsupported replay version {}", v),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(e: std::io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ReplayError {
+    fn from(e: serde_json::Error) -> Self {
+        ReplayError::Json(e)
+    }
+}
+
+impl Replay {
+    pub fn new(map: &str) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            map: map.to_string(),
+            commands: vec![],
+        }
+    }
+
+    pub fn from_json_file(path: &str) -> Result<Replay, ReplayError> {
+        let data = std::fs::read_to_string(path)?;
+        let replay: Replay = serde_json::from_str(&data)?;
+
+        if replay.version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(replay.version));
+        }
+
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ReplayError> {
+        let data = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+/// Records the commands applied by the simulation, saving them to `path` as they come
+pub struct ReplayRecorderPlugin {
+    pub map: String,
+    pub path: Option<String>,
+}
+
+pub struct ReplayRecorder {
+    pub replay: Replay,
+    pub path: Option<String>,
+}
+
+impl Plugin for ReplayRecorderPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(ReplayRecorder {
+            replay: Replay::new(&self.map),
+            path: self.path.clone(),
+        })
+        .add_system_to_stage(stage::POST_UPDATE, record_system.system());
+    }
+}
+
+/// Plays a replay back instead of listening to the players
+pub struct ReplayPlaybackPlugin {
+    pub replay: Replay,
+}
+
+pub struct ReplayPlayback {
+    pub replay: Replay,
+    /// Starts over from the first tick then seeks to the given tick, which is how we seek backward
+    pub restart: Option<u64>,
+}
+
+impl Plugin for ReplayPlaybackPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(ReplayPlayback {
+            replay: self.replay.clone(),
+            restart: None,
+        })
+        .add_startup_system(schedule_replay_system.system())
+        .add_system_to_stage(stage::POST_UPDATE, restart_system.system());
+    }
+}
+
+/// Keyboard controls for replays: space pauses, `,` and `.` change the speed,
+/// `[` and `]` seek backward and forward.
+pub struct ReplayControlsPlugin;
+
+impl Plugin for ReplayControlsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(controls_system.system());
+    }
+}
+
+fn record_system(mut recorder: ResMut<ReplayRecorder>, mut reader: EventReader<TickCommand>) {
+    let mut recorded = false;
+
+    for tick_command in reader.iter() {
+        recorder.replay.commands.push(tick_command.clone());
+        recorded = true;
+    }
+
+    if recorded {
+        if let Some(path) = &recorder.path {
+            if let Err(e) = recorder.replay.save(path) {
+                error!("Failed to save replay: {}", e);
+            }
+        }
+    }
+}
+
+fn schedule_replay_system(playback: Res<ReplayPlayback>, mut queue: ResMut<CommandQueue>) {
+    schedule_replay(&playback.replay, &mut queue);
+}
+
+fn schedule_replay(replay: &Replay, queue: &mut CommandQueue) {
+    for tick_command in replay.commands.iter() {
+        queue.schedule(
+            tick_command.tick,
+            tick_command.player,
+            tick_command.command.clone(),
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn restart_system(
+    commands: &mut Commands,
+    mut playback: ResMut<ReplayPlayback>,
+    mut tick: ResMut<Tick>,
+    mut clock: ResMut<SimulationClock>,
+    mut queue: ResMut<CommandQueue>,
+    archetypes: Res<UnitArchetypes>,
+    players: Res<Players>,
+    map: Res<Map>,
+    query: Query<Entity, With<Unit>>,
+    projectiles: Query<Entity, With<Projectile>>,
+) {
+    let seek_to = match playback.restart.take() {
+        Some(seek_to) => seek_to,
+        None => return,
+    };
+
+    for entity in query.iter().chain(projectiles.iter()) {
+        commands.despawn(entity);
+    }
+    unit::spawn_initial_units(commands, &archetypes, &players, &map);
+
+    tick.0 = 0;
+    queue.clear();
+    schedule_replay(&playback.replay, &mut queue);
+
+    clock.seek_to = Some(seek_to);
+}
+
+fn controls_system(
+    keys: Res<Input<KeyCode>>,
+    tick: Res<Tick>,
+    mut clock: ResMut<SimulationClock>,
+    mut playback: ResMut<ReplayPlayback>,
+) {
+    if keys.just_pressed(KeyCode::Space) {
+        clock.paused = !clock.paused;
+    }
+
+    if keys.just_pressed(KeyCode::Period) {
+        clock.speed = (clock.speed * 2.0).min(MAX_SPEED);
+    }
+
+    if keys.just_pressed(KeyCode::Comma) {
+        clock.speed = (clock.speed / 2.0).max(MIN_SPEED);
+    }
+
+    let seek_ticks = SEEK_SECONDS * TICKS_PER_SECOND as u64;
+
+    if keys.just_pressed(KeyCode::RBracket) {
+        clock.seek_to = Some(tick.0 + seek_ticks);
+    }
+
+    if keys.just_pressed(KeyCode::LBracket) {
+        playback.restart = Some(tick.0.saturating_sub(seek_ticks));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_harness::TestApp;
+
+    #[test]
+    fn test_replay_reproduces_recorded_game() {
+        let mut recorded = TestApp::build(|app| {
+            app.add_plugin(ReplayRecorderPlugin {
+                map: "assets/basic_map.tmj".to_string(),
+                path: None,
+            });
+        });
+
+        recorded.drag_select(Vec2::new(-150.0, -150.0), Vec2::new(150.0, 150.0));
+        recorded.right_click(Vec2::new(-300.0, -250.0));
+        recorded.step_seconds(2.0);
+        recorded.right_click(Vec2::new(300.0, 200.0));
+        recorded.step_seconds(3.0);
+
+        let replay = recorded
+            .app
+            .resources
+            .get::<ReplayRecorder>()
+            .expect("Missing ReplayRecorder")
+            .replay
+            .clone();
+        assert_eq!(3, replay.commands.len());
+
+        // Goes through serialization like a replay file would
+        let replay: Replay =
+            serde_json::from_str(&serde_json::to_string(&replay).expect("Failed to serialize"))
+                .expect("Failed to deserialize");
+
+        let mut replayed = TestApp::build(|app| {
+            app.add_plugin(ReplayPlaybackPlugin { replay });
+        });
+        while replayed.tick() < recorded.tick() {
+            replayed.step();
+        }
+
+        assert_eq!(recorded.unit_positions(), replayed.unit_positions());
+    }
+}
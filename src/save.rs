@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use bevy::app::stage;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::animation::Animations;
+use crate::combat::{Armor, Attack, AttackOrder, Dying, Health, TargetFilter};
+use crate::command::{CommandQueue, TickCommand};
+use crate::math::Vector;
+use crate::path_finding::grid::{Grid, TileType};
+use crate::player::{Owner, PlayerId};
+use crate::projectile::{self, Projectile, ProjectileKind};
+use crate::simulation::{MapPath, Tick};
+use crate::tiled::Map;
+use crate::unit::{AttackMoveOrder, MoveOrder, Position, Unit};
+
+pub const SAVE_VERSION: u32 = 7;
+
+const QUICKSAVE_PATH: &str = "saves/quicksave.json";
+
+/// The whole simulation state
+/// Units are stored in the order the simulation iterates them, which decides who wins collisions.
+/// The map is stored by reference, along with the tiles changed at runtime.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    pub map: String,
+    pub tick: u64,
+    pub grid_changes: Vec<((i32, i32), TileType)>,
+    pub pending_commands: Vec<TickCommand>,
+    pub units: Vec<SavedUnit>,
+    pub projectiles: Vec<SavedProjectile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedUnit {
+    pub owner: Owner,
+    pub unit: Unit,
+    pub position: Position,
+    pub move_order: MoveOrder,
+    pub health: Health,
+    pub armor: Option<Armor>,
+    pub attack: Option<Attack>,
+    /// Index of the unit it was ordered to attack
+    pub attack_target: Option<usize>,
+    /// Where it was attack-moving to
+    pub attack_move: Option<Vector>,
+    pub dying: Option<Dying>,
+    /// Only units with a sprite are animated
+    pub animations: Option<Animations>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedProjectile {
+    pub owner: PlayerId,
+    pub kind: ProjectileKind,
+    pub damage: i32,
+    pub targets: TargetFilter,
+    /// Index of the unit it follows
+    pub target: Option<usize>,
+    pub origin: Vector,
+    pub destination: Vector,
+    pub position: Position,
+}
+
+/// Combat components of a unit, as queried to save it
+pub type SavedCombat<'a> = (
+    &'a Health,
+    Option<&'a Armor>,
+    Option<&'a Attack>,
+    Option<&'a AttackOrder>,
+    Option<&'a AttackMoveOrder>,
+    Option<&'a Dying>,
+);
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    WrongMap { expected: String, found: String },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "save io error: {}", e),
+            SaveError::Json(e) => write!(f, "invalid save: {}", e),
+            SaveError::UnsupportedVersion(v) => write!(f, "unsupported save version {}", v),
+            SaveError::WrongMap { expected, found } => write!(
+                f,
+                "save was made on map {}, the current map is {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<std::io::Error> for SaveError {
+    fn from(e: std::io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(e: serde_json::Error) -> Self {
+        SaveError::Json(e)
+    }
+}
+
+impl SaveGame {
+    pub fn from_json_file(path: &str) -> Result<SaveGame, SaveError> {
+        let data = std::fs::read_to_string(path)?;
+        let save: SaveGame = serde_json::from_str(&data)?;
+
+        if save.version != SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion(save.version));
+        }
+
+        Ok(save)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), SaveError> {
+        if let Some(directory) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+
+        let data = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Captures the state, `units` in simulation order
+    pub fn capture<'a>(
+        map_path: &MapPath,
+        map: &Map,
+        tick: &Tick,
+        grid: &Grid,
+        queue: &CommandQueue,
+        units: impl Iterator<
+            Item = (
+                Entity,
+                &'a Owner,
+                &'a Unit,
+                &'a Position,
+                &'a MoveOrder,
+                SavedCombat<'a>,
+                Option<&'a Animations>,
+            ),
+        >,
+        projectiles: impl Iterator<Item = (&'a Projectile, &'a Position)>,
+    ) -> SaveGame {
+        let original_grid = Grid::from_tiled_map(map).expect("Failed to generate collision grid");
+        let units: Vec<_> = units.collect();
+
+        // Entities are not the same once loaded, units refer to each other by index
+        let indices: HashMap<Entity, usize> = units
+            .iter()
+            .enumerate()
+            .map(|(index, (entity, ..))| (*entity, index))
+            .collect();
+
+        SaveGame {
+            version: SAVE_VERSION,
+            map: map_path.0.clone(),
+            tick: tick.0,
+            grid_changes: original_grid.changes(grid),
+            pending_commands: queue.pending(),
+            units: units
+                .into_iter()
+                .map(
+                    |(_, owner, unit, position, move_order, combat, animations)| {
+                        let (health, armor, attack, attack_order, attack_move, dying) = combat;
+
+                        SavedUnit {
+                            owner: *owner,
+                            unit: unit.clone(),
+                            position: position.clone(),
+                            move_order: move_order.clone(),
+                            health: health.clone(),
+                            armor: armor.cloned(),
+                            attack: attack.cloned(),
+                            attack_target: attack_order
+                                .and_then(|order| order.target)
+                                .and_then(|target| indices.get(&target).cloned()),
+                            attack_move: attack_move.and_then(|order| order.target),
+                            dying: dying.cloned(),
+                            animations: animations.cloned(),
+                        }
+                    },
+                )
+                .collect(),
+            projectiles: projectiles
+                .map(|(projectile, position)| SavedProjectile {
+                    owner: projectile.owner,
+                    kind: projectile.kind.clone(),
+                    damage: projectile.damage,
+                    targets: projectile.targets,
+                    target: projectile
+                        .target
+                        .and_then(|target| indices.get(&target).cloned()),
+                    origin: projectile.origin,
+                    destination: projectile.destination,
+                    position: position.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Saves the game to the given path
+pub struct SaveRequest(pub String);
+
+/// Replaces the current game with a saved one
+pub struct LoadRequest(pub SaveGame);
+
+/// Handles `SaveRequest` and `LoadRequest` events
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<SaveRequest>()
+            .add_event::<LoadRequest>()
+            .add_system_to_stage(stage::POST_UPDATE, save_system.system())
+            .add_system_to_stage(stage::POST_UPDATE, load_system.system());
+    }
+}
+
+/// F5 quick saves, F9 quick loads
+pub struct SaveInputPlugin;
+
+impl Plugin for SaveInputPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(save_input_system.system());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn save_system(
+    mut reader: EventReader<SaveRequest>,
+    map_path: Res<MapPath>,
+    map: Res<Map>,
+    tick: Res<Tick>,
+    grid: Res<Grid>,
+    queue: Res<CommandQueue>,
+    query: Query<(
+        Entity,
+        &Owner,
+        &Unit,
+        &Position,
+        &MoveOrder,
+        SavedCombat,
+        Option<&Animations>,
+    )>,
+    projectiles: Query<(&Projectile, &Position)>,
+) {
+    for SaveRequest(path) in reader.iter() {
+        let save = SaveGame::capture(
+            &map_path,
+            &map,
+            &tick,
+            &grid,
+            &queue,
+            query.iter(),
+            projectiles.iter(),
+        );
+
+        match save.save(path) {
+            Ok(()) => info!("Saved game to {}", path),
+            Err(e) => error!("Failed to save game: {}", e),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn load_system(
+    commands: &mut Commands,
+    mut reader: EventReader<LoadRequest>,
+    map_path: Res<MapPath>,
+    map: Res<Map>,
+    mut tick: ResMut<Tick>,
+    mut grid: ResMut<Grid>,
+    mut queue: ResMut<CommandQueue>,
+    query: Query<Entity, With<Unit>>,
+    projectiles: Query<Entity, With<Projectile>>,
+) {
+    for LoadRequest(save) in reader.iter() {
+        if save.map != map_path.0 {
+            error!(
+                "Failed to load game: {}",
+                SaveError::WrongMap {
+                    expected: map_path.0.clone(),
+                    found: save.map.clone(),
+                }
+            );
+            continue;
+        }
+
+        for entity in query.iter().chain(projectiles.iter()) {
+            commands.despawn(entity);
+        }
+
+        *grid = Grid::from_tiled_map(&map).expect("Failed to generate collision grid");
+        for (position, tile_type) in save.grid_changes.iter() {
+            grid.set(*position, *tile_type);
+        }
+
+        tick.0 = save.tick;
+
+        queue.clear();
+        for tick_command in save.pending_commands.iter() {
+            queue.schedule(
+                tick_command.tick,
+                tick_command.player,
+                tick_command.command.clone(),
+            );
+        }
+
+        let mut entities = vec![];
+
+        for saved in save.units.iter() {
+            commands.spawn((
+                saved.owner,
+                saved.unit.clone(),
+                saved.position.clone(),
+                saved.move_order.clone(),
+                saved.health.clone(),
+            ));
+
+            if let Some(armor) = saved.armor {
+                commands.with(armor);
+            }
+            if let Some(attack) = &saved.attack {
+                commands
+                    .with(attack.clone())
+                    .with(AttackOrder::default())
+                    .with(AttackMoveOrder {
+                        target: saved.attack_move,
+                    });
+            }
+            if let Some(dying) = &saved.dying {
+                commands.with(dying.clone());
+            }
+            if let Some(animations) = &saved.animations {
+                commands.with(animations.clone());
+            }
+
+            entities.push(commands.current_entity().expect("Missing spawned unit"));
+        }
+
+        // Now that every unit has its entity
+        for (saved, entity) in save.units.iter().zip(entities.iter()) {
+            if saved.attack_target.is_some() {
+                let target = saved
+                    .attack_target
+                    .and_then(|index| entities.get(index).cloned());
+                commands.insert_one(*entity, AttackOrder { target });
+            }
+        }
+
+        for saved in save.projectiles.iter() {
+            projectile::spawn_projectile(
+                commands,
+                Projectile {
+                    owner: saved.owner,
+                    kind: saved.kind.clone(),
+                    damage: saved.damage,
+                    targets: saved.targets,
+                    target: saved.target.and_then(|index| entities.get(index).cloned()),
+                    origin: saved.origin,
+                    destination: saved.destination,
+                },
+                saved.position.0,
+            );
+        }
+    }
+}
+
+fn save_input_system(
+    keys: Res<Input<KeyCode>>,
+    mut save_requests: ResMut<Events<SaveRequest>>,
+    mut load_requests: ResMut<Events<LoadRequest>>,
+) {
+    if keys.just_pressed(KeyCode::F5) {
+        save_requests.send(SaveRequest(QUICKSAVE_PATH.to_string()));
+    }
+
+    if keys.just_pressed(KeyCode::F9) {
+        match SaveGame::from_json_file(QUICKSAVE_PATH) {
+            Ok(save) => load_requests.send(LoadRequest(save)),
+            Err(e) => error!("Failed to load game: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::PlayerCommand;
+    use crate::math::Vector;
+    use crate::test_harness::TestApp;
+
+    #[test]
+    fn test_save_load_save_round_trip() {
+        let mut app = TestApp::new();
+
+        app.drag_select(Vec2::new(-150.0, -150.0), Vec2::new(150.0, 150.0));
+        app.right_click(Vec2::new(300.0, 200.0));
+        app.step_seconds(1.0);
+        // Still pending when saving
+        app.send_command(PlayerCommand::Move {
+            target: Vector::from(Vec2::new(-300.0, -250.0)),
+        });
+        app.step();
+
+        let saved = app.save_game();
+
+        // Goes through serialization like a save file would
+        let loaded: SaveGame =
+            serde_json::from_str(&serde_json::to_string(&saved).expect("Failed to serialize"))
+                .expect("Failed to deserialize");
+
+        let mut restored = TestApp::new();
+        restored.step_seconds(0.5);
+        restored.load_game(loaded);
+
+        assert_eq!(saved, restored.save_game());
+
+        // And both games keep going the same way
+        app.step_seconds(2.0);
+        restored.step_seconds(2.0);
+        assert_eq!(app.unit_positions(), restored.unit_positions());
+    }
+
+    #[test]
+    fn test_load_rejects_other_maps() {
+        let mut app = TestApp::new();
+        let mut save = app.save_game();
+        save.map = "assets/other_map.json".to_string();
+        save.units.clear();
+
+        app.load_game(save);
+        assert_eq!(4, app.unit_positions().len());
+    }
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+
+use bevy::prelude::*;
+
+use crate::command::{PlayerCommand, TickCommand};
+use crate::math::Vector;
+use crate::player::PlayerId;
+
+/// `PlayerCommand`s the first player issues at given ticks, one per line:
+///
+/// ```text
+/// # tick command arguments
+/// 1 select -150 -150 150 150
+/// 1 move 300 200
+/// 90 stop
+/// 120 attack 23 42
+/// 200 attack-move -200 0
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Script {
+    pub commands: Vec<TickCommand>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+impl Script {
+    pub fn from_file(path: &str) -> Result<Script, ScriptError> {
+        let data = std::fs::read_to_string(path).map_err(|e| ScriptError {
+            line: 0,
+            message: format!("unable to read {}: {}", path, e),
+        })?;
+        Script::parse(&data)
+    }
+
+    pub fn parse(data: &str) -> Result<Script, ScriptError> {
+        let mut script = Script::default();
+
+        for (index, line) in data.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: &str| ScriptError {
+                line: line_number,
+                message: message.to_string(),
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+
+            let tick = words
+                .next()
+                .and_then(|w| w.parse::<u64>().ok())
+                .ok_or_else(|| error("expected a tick number"))?;
+            let name = words.next().ok_or_else(|| error("expected a command"))?;
+            let arguments = words
+                .map(|w| w.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|_| error("arguments must be numbers"))?;
+
+            let command = match (name, arguments.as_slice()) {
+                ("select", [min_x, min_y, max_x, max_y]) => PlayerCommand::Select {
+                    min: Vector::from(Vec2::new(*min_x, *min_y)),
+                    max: Vector::from(Vec2::new(*max_x, *max_y)),
+                },
+                ("move", [x, y]) => PlayerCommand::Move {
+                    target: Vector::from(Vec2::new(*x, *y)),
+                },
+                ("stop", []) => PlayerCommand::Stop,
+                ("attack", [x, y]) => PlayerCommand::Attack {
+                    target: Vector::from(Vec2::new(*x, *y)),
+                },
+                ("attack-move", [x, y]) => PlayerCommand::AttackMove {
+                    target: Vector::from(Vec2::new(*x, *y)),
+                },
+                _ => return Err(error(&format!("invalid command `{}`", line))),
+            };
+
+            script.commands.push(TickCommand {
+                tick,
+                player: PlayerId(0),
+                command,
+            });
+        }
+
+        Ok(script)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_script() {
+        let script = Script::parse(
+            "# select everything then move\n\
+             1 select -150 -150 150 150\n\
+             \n\
+             3 move 300 200\n\
+             4 stop\n",
+        )
+        .expect("Failed to parse script");
+
+        assert_eq!(
+            vec![
+                TickCommand {
+                    tick: 1,
+                    player: PlayerId(0),
+                    command: PlayerCommand::Select {
+                        min: Vector::from(Vec2::new(-150.0, -150.0)),
+                        max: Vector::from(Vec2::new(150.0, 150.0)),
+                    },
+                },
+                TickCommand {
+                    tick: 3,
+                    player: PlayerId(0),
+                    command: PlayerCommand::Move {
+                        target: Vector::from(Vec2::new(300.0, 200.0)),
+                    },
+                },
+                TickCommand {
+                    tick: 4,
+                    player: PlayerId(0),
+                    command: PlayerCommand::Stop,
+                },
+            ],
+            script.commands
+        );
+    }
+
+    #[test]
+    fn test_parse_script_error() {
+        let error = Script::parse("1 select 0 0\n").expect_err("Should not parse");
+        assert_eq!(1, error.line);
+    }
+}
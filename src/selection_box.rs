@@ -1,7 +1,9 @@
 use bevy::prelude::*;
 
+use crate::command::PlayerCommand;
+use crate::math::Vector;
 use crate::mouse_position::MouseWorldPosition;
-use crate::unit::Unit;
+use crate::orders::AttackMoveCursor;
 
 pub struct SelectionBoxPlugin;
 
@@ -12,7 +14,8 @@ pub struct SelectionBox {
 
 impl Plugin for SelectionBoxPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_system(setup.system())
+        app.init_resource::<AttackMoveCursor>()
+            .add_startup_system(setup.system())
             .add_system(selection_box_system.system());
     }
 }
@@ -38,13 +41,14 @@ fn setup(commands: &mut Commands, mut materials: ResMut<Assets<ColorMaterial>>)
 fn selection_box_system(
     mouse_position: Res<MouseWorldPosition>,
     mouse_buttons: Res<Input<MouseButton>>,
+    attack_move_cursor: Res<AttackMoveCursor>,
+    mut player_commands: ResMut<Events<PlayerCommand>>,
     mut query: Query<(&mut SelectionBox, &mut Transform, &mut Sprite, &mut Visible)>,
-    mut unit_query: Query<(&Transform, &mut Unit, &mut TextureAtlasSprite)>,
 ) {
     if let Some((mut selection_box, mut transform, mut sprite, mut visible)) =
         query.iter_mut().next()
     {
-        if mouse_buttons.just_pressed(MouseButton::Left) {
+        if mouse_buttons.just_pressed(MouseButton::Left) && !attack_move_cursor.0 {
             selection_box.active = true;
             selection_box.initial_position = mouse_position.0;
             visible.is_visible = true;
@@ -66,17 +70,10 @@ fn selection_box_system(
 
                 debug!("Selection Box {} {} {} {}", min_x, max_x, min_y, max_y);
 
-                for (unit_transform, mut unit, mut texture_atlas_sprite) in unit_query.iter_mut() {
-                    let trans = unit_transform.translation;
-
-                    if trans.x > min_x && trans.x < max_x && trans.y > min_y && trans.y < max_y {
-                        unit.selected = true;
-                        texture_atlas_sprite.color = Color::RED;
-                    } else {
-                        unit.selected = false;
-                        texture_atlas_sprite.color = Color::WHITE;
-                    }
-                }
+                player_commands.send(PlayerCommand::Select {
+                    min: Vector::from(Vec2::new(min_x, min_y)),
+                    max: Vector::from(Vec2::new(max_x, max_y)),
+                });
 
                 selection_box.active = false;
                 visible.is_visible = false;
@@ -0,0 +1,170 @@
+use bevy::app::stage;
+use bevy::ecs::ShouldRun;
+use bevy::prelude::*;
+
+use crate::math::{Real, Scalar};
+
+pub const SIMULATION_STAGE: &str = "simulation";
+pub const TICKS_PER_SECOND: i32 = 60;
+
+// Keeps the app responsive while seeking
+const MAX_SEEK_TICKS_PER_FRAME: u32 = 600;
+
+/// How the simulation stage is scheduled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TickMode {
+    /// Runs at `TICKS_PER_SECOND`, catching up when frames take longer than a tick
+    RealTime,
+    /// Runs exactly once per app update, as fast as possible (headless runs, tests)
+    PerUpdate,
+}
+
+/// Number of the simulation tick being run, starting at 1
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Tick(pub u64);
+
+/// Path of the Tiled map the game is played on
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapPath(pub String);
+
+/// Sent once the asset server loaded the map, the `Map` and `Grid` resources hold it
+/// Maps inserted before the app runs are loaded already, nothing is sent for them.
+pub struct MapLoaded;
+
+/// Playback controls for `TickMode::RealTime`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationClock {
+    pub speed: f64,
+    pub paused: bool,
+    /// Runs ticks as fast as possible until this tick is reached
+    pub seek_to: Option<u64>,
+    /// Last tick allowed to run, networking holds the simulation back until every peer's
+    /// commands for the coming ticks arrived
+    pub max_tick: Option<u64>,
+    /// No tick runs until the map is loaded, cleared by `MapLoaded`
+    pub loading_map: bool,
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            paused: false,
+            seek_to: None,
+            max_tick: None,
+            loading_map: false,
+        }
+    }
+}
+
+/// Creates the fixed rate simulation stage, runs in `TickMode::RealTime` unless
+/// another mode was inserted before the plugin is added, the same goes for the `SimulationClock`.
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        if !app.resources().contains::<TickMode>() {
+            app.insert_resource(TickMode::RealTime);
+        }
+
+        if !app.resources().contains::<SimulationClock>() {
+            app.insert_resource(SimulationClock::default());
+        }
+        if !app.resources().contains::<Events<MapLoaded>>() {
+            app.add_event::<MapLoaded>();
+        }
+
+        // The simulation runs at a fixed rate so every machine computes the same
+        // positions for the same orders, no matter its frame rate.
+        app.insert_resource(Tick::default())
+            .add_system(map_loaded_system.system())
+            .add_stage_after(
+                stage::UPDATE,
+                SIMULATION_STAGE,
+                SystemStage::serial().with_run_criteria(tick_criteria.system()),
+            );
+    }
+}
+
+/// Duration of a single simulation tick
+pub fn timestep() -> Scalar {
+    Scalar::from_int(1) / Scalar::from_int(TICKS_PER_SECOND)
+}
+
+fn map_loaded_system(mut reader: EventReader<MapLoaded>, mut clock: ResMut<SimulationClock>) {
+    if reader.iter().next().is_some() {
+        clock.loading_map = false;
+    }
+}
+
+#[derive(Default)]
+struct TickAccumulator {
+    accumulator: f64,
+    looping: bool,
+    seek_ticks: u32,
+}
+
+fn tick_criteria(
+    mode: Res<TickMode>,
+    time: Res<Time>,
+    mut tick: ResMut<Tick>,
+    mut clock: ResMut<SimulationClock>,
+    mut state: Local<TickAccumulator>,
+) -> ShouldRun {
+    if clock.loading_map {
+        return ShouldRun::No;
+    }
+
+    if let Some(max_tick) = clock.max_tick {
+        if tick.0 >= max_tick {
+            state.looping = false;
+            return ShouldRun::No;
+        }
+    }
+
+    match *mode {
+        TickMode::PerUpdate => {
+            tick.0 += 1;
+            ShouldRun::Yes
+        }
+        TickMode::RealTime => {
+            let step = 1.0 / TICKS_PER_SECOND as f64;
+
+            if let Some(target) = clock.seek_to {
+                if tick.0 < target && state.seek_ticks < MAX_SEEK_TICKS_PER_FRAME {
+                    state.seek_ticks += 1;
+                    tick.0 += 1;
+                    return ShouldRun::YesAndLoop;
+                }
+
+                if tick.0 >= target {
+                    clock.seek_to = None;
+                }
+
+                state.seek_ticks = 0;
+                state.accumulator = 0.0;
+                state.looping = false;
+                return ShouldRun::No;
+            }
+
+            if clock.paused {
+                state.looping = false;
+                return ShouldRun::No;
+            }
+
+            if !state.looping {
+                state.accumulator += time.delta_seconds_f64() * clock.speed;
+            }
+
+            if state.accumulator >= step {
+                state.accumulator -= step;
+                state.looping = true;
+                tick.0 += 1;
+                ShouldRun::YesAndLoop
+            } else {
+                state.looping = false;
+                ShouldRun::No
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::math::{Real, Scalar, Vector};
+
+/// Buckets entities by position so range queries only look at the nearby ones
+/// Results come in insertion order, which keeps them the same on every machine.
+pub struct SpatialGrid {
+    cell_size: Scalar,
+    cells: HashMap<(i32, i32), Vec<(usize, Entity, Vector)>>,
+    len: usize,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: Scalar) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    pub fn insert(&mut self, entity: Entity, position: Vector) {
+        let cell = self.cell(position);
+        self.cells
+            .entry(cell)
+            .or_default()
+            .push((self.len, entity, position));
+        self.len += 1;
+    }
+
+    /// Entities at most `range` away from `center`, with their position
+    pub fn in_range(&self, center: Vector, range: Scalar) -> Vec<(Entity, Vector)> {
+        let reach = Vector::new(range, range);
+        let (min_x, min_y) = self.cell(center - reach);
+        let (max_x, max_y) = self.cell(center + reach);
+        let range_squared = range * range;
+
+        let mut found: Vec<(usize, Entity, Vector)> = (min_x..=max_x)
+            .flat_map(|x| (min_y..=max_y).map(move |y| (x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter(|(_, _, position)| (*position - center).length_squared() <= range_squared)
+            .cloned()
+            .collect();
+        found.sort_by_key(|(order, ..)| *order);
+
+        found
+            .into_iter()
+            .map(|(_, entity, position)| (entity, position))
+            .collect()
+    }
+
+    fn cell(&self, position: Vector) -> (i32, i32) {
+        let cell_size = self.cell_size.to_f32();
+        (
+            (position.x.to_f32() / cell_size).floor() as i32,
+            (position.y.to_f32() / cell_size).floor() as i32,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_range() {
+        let mut grid = SpatialGrid::new(Scalar::from_int(32));
+        let entities: Vec<Entity> = (0..4).map(Entity::new).collect();
+
+        grid.insert(entities[0], Vector::from(Vec2::new(100.0, 0.0)));
+        grid.insert(entities[1], Vector::from(Vec2::new(-30.0, 20.0)));
+        grid.insert(entities[2], Vector::from(Vec2::new(500.0, 500.0)));
+        grid.insert(entities[3], Vector::from(Vec2::new(10.0, -10.0)));
+
+        let found: Vec<Entity> = grid
+            .in_range(Vector::zero(), Scalar::from_int(40))
+            .into_iter()
+            .map(|(entity, _)| entity)
+            .collect();
+
+        assert_eq!(vec![entities[1], entities[3]], found);
+    }
+}
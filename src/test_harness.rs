@@ -0,0 +1,177 @@
+use bevy::asset::AssetPlugin;
+use bevy::core::CorePlugin;
+use bevy::prelude::*;
+
+use crate::animation::Animations;
+use crate::archetype::{UnitArchetypes, UNITS_DIRECTORY};
+use crate::command::{CommandQueue, PlayerCommand};
+use crate::mouse_position::MouseWorldPosition;
+use crate::orders::OrderInputPlugin;
+use crate::path_finding::grid::Grid;
+use crate::player::Owner;
+use crate::plugins::SimulationPlugins;
+use crate::projectile::Projectile;
+use crate::save::{LoadRequest, SaveGame, SavedCombat};
+use crate::selection_box::SelectionBoxPlugin;
+use crate::simulation::{MapPath, Tick, TickMode, TICKS_PER_SECOND};
+use crate::tiled;
+use crate::unit::{MoveOrder, Position, Unit};
+
+/// A windowless `App` running the simulation and the player input systems,
+/// with the mouse driven by the test instead of the window.
+/// Every `step` runs exactly one simulation tick.
+pub struct TestApp {
+    pub app: App,
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        Self::build(|_| {})
+    }
+
+    /// Same as `new` with extra plugins or resources from `configure`
+    pub fn build(configure: impl FnOnce(&mut AppBuilder)) -> Self {
+        let map_path = "assets/basic_map.tmj";
+        let map = tiled::Map::from_file(map_path).expect("Couldnt load map");
+        let grid = Grid::from_tiled_map(&map).expect("Failed to generate collision grid");
+        let archetypes =
+            UnitArchetypes::load_directory(UNITS_DIRECTORY).expect("Failed to load archetypes");
+
+        let mut builder = App::build();
+        builder
+            .insert_resource(TickMode::PerUpdate)
+            .insert_resource(MouseWorldPosition::default())
+            .insert_resource(Input::<MouseButton>::default())
+            .insert_resource(Input::<KeyCode>::default())
+            .insert_resource(MapPath(map_path.to_string()))
+            .insert_resource(grid)
+            .insert_resource(map)
+            .insert_resource(archetypes)
+            .add_plugin(CorePlugin)
+            // Order and selection input spawn debug sprites, they need materials but no renderer
+            .add_plugin(AssetPlugin)
+            .add_asset::<ColorMaterial>()
+            .add_plugins(SimulationPlugins)
+            .add_plugin(SelectionBoxPlugin)
+            .add_plugin(OrderInputPlugin);
+        configure(&mut builder);
+
+        let mut test_app = Self { app: builder.app };
+        // Run the startup systems
+        test_app.step();
+        test_app
+    }
+
+    pub fn step(&mut self) {
+        self.app.update();
+    }
+
+    pub fn step_seconds(&mut self, seconds: f32) {
+        let ticks = (seconds * TICKS_PER_SECOND as f32).ceil() as usize;
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.app.resources.get::<Tick>().expect("Missing Tick").0
+    }
+
+    pub fn move_mouse(&mut self, position: Vec2) {
+        let mut mouse_position = self
+            .app
+            .resources
+            .get_mut::<MouseWorldPosition>()
+            .expect("Missing MouseWorldPosition");
+        mouse_position.0 = position.extend(0.0);
+    }
+
+    /// Presses `button` for one frame, `just_pressed` is only true during that frame
+    pub fn press(&mut self, button: MouseButton) {
+        self.mouse_buttons(|buttons| buttons.press(button));
+        self.step();
+        self.mouse_buttons(|buttons| buttons.update());
+    }
+
+    pub fn release(&mut self, button: MouseButton) {
+        self.mouse_buttons(|buttons| buttons.release(button));
+        self.step();
+        self.mouse_buttons(|buttons| buttons.update());
+    }
+
+    /// Box selects the units between `from` and `to`, like a left click drag
+    pub fn drag_select(&mut self, from: Vec2, to: Vec2) {
+        self.move_mouse(from);
+        self.press(MouseButton::Left);
+        self.move_mouse(to);
+        self.step();
+        self.release(MouseButton::Left);
+    }
+
+    pub fn right_click(&mut self, at: Vec2) {
+        self.move_mouse(at);
+        self.press(MouseButton::Right);
+        self.release(MouseButton::Right);
+    }
+
+    /// Issues a command like the input systems do, it runs on the tick after the next update
+    pub fn send_command(&mut self, command: PlayerCommand) {
+        self.app
+            .resources
+            .get_mut::<Events<PlayerCommand>>()
+            .expect("Missing Events<PlayerCommand>")
+            .send(command);
+    }
+
+    /// Unit positions in simulation order, which is spawn order for the initial units
+    pub fn unit_positions(&self) -> Vec<Vec2> {
+        self.app
+            .world
+            .query::<(&Position, &Unit)>()
+            .map(|(position, _)| position.0.to_vec2())
+            .collect()
+    }
+
+    pub fn save_game(&self) -> SaveGame {
+        let resources = &self.app.resources;
+
+        SaveGame::capture(
+            &resources.get::<MapPath>().expect("Missing MapPath"),
+            &resources.get::<tiled::Map>().expect("Missing Map"),
+            &resources.get::<Tick>().expect("Missing Tick"),
+            &resources.get::<Grid>().expect("Missing Grid"),
+            &resources
+                .get::<CommandQueue>()
+                .expect("Missing CommandQueue"),
+            self.app.world.query::<(
+                Entity,
+                &Owner,
+                &Unit,
+                &Position,
+                &MoveOrder,
+                SavedCombat,
+                Option<&Animations>,
+            )>(),
+            self.app.world.query::<(&Projectile, &Position)>(),
+        )
+    }
+
+    /// Loads `save`, it replaces the current game by the end of the update
+    pub fn load_game(&mut self, save: SaveGame) {
+        self.app
+            .resources
+            .get_mut::<Events<LoadRequest>>()
+            .expect("Missing Events<LoadRequest>")
+            .send(LoadRequest(save));
+        self.step();
+    }
+
+    fn mouse_buttons(&mut self, f: impl FnOnce(&mut Input<MouseButton>)) {
+        let mut buttons = self
+            .app
+            .resources
+            .get_mut::<Input<MouseButton>>()
+            .expect("Missing Input<MouseButton>");
+        f(&mut buttons);
+    }
+}
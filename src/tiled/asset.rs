@@ -0,0 +1,62 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use bevy::asset::{AssetLoader, AssetServerSettings, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+
+use super::{MapError, MapLoader};
+
+/// Loads `.tmx` maps and `.tmj` JSON exports as `Map` assets, other `.json` files aren't maps
+/// Changing only a tileset doesn't reload the map, the next reload reads the tileset again.
+pub struct TiledMapLoader {
+    /// The asset server's asset folder, external tilesets are read from there too
+    asset_folder: PathBuf,
+    /// Shared by every load, so tilesets are only read again once their file changed
+    loader: Mutex<MapLoader>,
+}
+
+impl TiledMapLoader {
+    pub const EXTENSIONS: &'static [&'static str] = &["tmx", "tmj"];
+}
+
+impl FromResources for TiledMapLoader {
+    fn from_resources(resources: &Resources) -> Self {
+        let asset_folder = resources.get::<AssetServerSettings>().map_or_else(
+            || AssetServerSettings::default().asset_folder,
+            |settings| settings.asset_folder.clone(),
+        );
+
+        TiledMapLoader {
+            asset_folder: PathBuf::from(asset_folder),
+            loader: Mutex::new(MapLoader::default()),
+        }
+    }
+}
+
+impl AssetLoader for TiledMapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let data = std::str::from_utf8(bytes)
+                .map_err(|e| MapError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+            let path = self.asset_folder.join(load_context.path());
+            let map = self
+                .loader
+                .lock()
+                .expect("Map loader poisoned")
+                .parse(data, &path)?;
+
+            load_context.set_default_asset(LoadedAsset::new(map));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        Self::EXTENSIONS
+    }
+}
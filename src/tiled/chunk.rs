@@ -0,0 +1,119 @@
+use super::TileRef;
+
+/// A rectangle of tiles, from the top left corner of the map
+/// Infinite maps place some of their tiles at negative positions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Bounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Bounds {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        self.x < other.x + other.width
+            && other.x < self.x + self.width
+            && self.y < other.y + other.height
+            && other.y < self.y + self.height
+    }
+
+    /// Smallest bounds covering both
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+
+        Bounds {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+
+    /// Cut in squares of `size` tiles, smaller along the right and bottom edges
+    pub fn split(&self, size: i32) -> Vec<Bounds> {
+        let mut parts = vec![];
+
+        for y in (self.y..self.y + self.height).step_by(size as usize) {
+            for x in (self.x..self.x + self.width).step_by(size as usize) {
+                parts.push(Bounds {
+                    x,
+                    y,
+                    width: size.min(self.x + self.width - x),
+                    height: size.min(self.y + self.height - y),
+                });
+            }
+        }
+
+        parts
+    }
+}
+
+/// Part of a tile layer, finite maps have a single one covering the whole layer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    /// In tiles from the top left corner of the map
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// Row by row
+    pub data: Vec<TileRef>,
+}
+
+impl Chunk {
+    /// Fails unless there's a tile for every cell
+    pub(super) fn new(bounds: Bounds, data: Vec<TileRef>) -> Result<Chunk, String> {
+        if data.len() != (bounds.width * bounds.height) as usize {
+            return Err(format!(
+                "{} tiles in a {}x{} chunk",
+                data.len(),
+                bounds.width,
+                bounds.height
+            ));
+        }
+
+        Ok(Chunk {
+            x: bounds.x,
+            y: bounds.y,
+            width: bounds.width,
+            height: bounds.height,
+            data,
+        })
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        Bounds {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// `x`, `y` in tiles from the top left corner of the map, empty outside of the chunk
+    pub fn tile(&self, x: i32, y: i32) -> TileRef {
+        if self.bounds().contains(x, y) {
+            self.data[((y - self.y) * self.width + x - self.x) as usize]
+        } else {
+            TileRef::default()
+        }
+    }
+
+    /// Every cell, with its position in tiles from the top left corner of the map
+    pub fn tiles(&self) -> impl Iterator<Item = (i32, i32, TileRef)> + '_ {
+        self.data.iter().enumerate().map(move |(index, tile)| {
+            let index = index as i32;
+            (
+                self.x + index % self.width,
+                self.y + index / self.width,
+                *tile,
+            )
+        })
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::Read;
+
+use serde::{Deserialize, Serialize};
+
+use super::TileRef;
+
+/// How the `data` of a tile layer is stored
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// A plain array of global tile ids
+    Csv,
+    /// Little endian global tile ids, compressed unless `Compression::None`
+    Base64,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Csv
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[serde(rename = "")]
+    None,
+    Zlib,
+    Gzip,
+    Zstd,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+/// Tiles of base64 encoded layer data
+pub fn decode_base64(data: &str, compression: Compression) -> Result<Vec<TileRef>, String> {
+    // Tiled keeps the line breaks of the XML format in some exports
+    let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+    let bytes = base64::decode(&data).map_err(|e| format!("invalid base64 layer data: {}", e))?;
+    let bytes = decompress(&bytes, compression)
+        .map_err(|e| format!("invalid {:?} layer data: {}", compression, e))?;
+
+    if bytes.len() % 4 != 0 {
+        return Err(format!(
+            "layer data of {} bytes isn't made of tile ids",
+            bytes.len()
+        ));
+    }
+
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|id| TileRef::from(u32::from_le_bytes([id[0], id[1], id[2], id[3]])))
+        .collect())
+}
+
+fn decompress(bytes: &[u8], compression: Compression) -> std::io::Result<Vec<u8>> {
+    let mut decompressed = vec![];
+
+    match compression {
+        Compression::None => decompressed.extend_from_slice(bytes),
+        Compression::Zlib => {
+            flate2::read::ZlibDecoder::new(bytes).read_to_end(&mut decompressed)?;
+        }
+        Compression::Gzip => {
+            flate2::read::GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+        }
+        Compression::Zstd => decompressed = zstd::stream::decode_all(bytes)?,
+    }
+
+    Ok(decompressed)
+}
@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+
+use super::encoding::{decode_base64, Compression, Encoding};
+use super::{default_visible, Bounds, Chunk, CustomProperties, Object, Property};
+
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+/// Only used by hexagonal maps, which we don't render
+const ROTATED_HEXAGONAL_120: u32 = 0x1000_0000;
+const FLAGS: u32 =
+    FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL_120;
+
+/// Fields every kind of layer has, the rest depends on its `kind`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layer {
+    pub id: i32,
+    pub name: String,
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    /// In pixels
+    #[serde(rename = "offsetx", default)]
+    pub offset_x: f32,
+    #[serde(rename = "offsety", default)]
+    pub offset_y: f32,
+    #[serde(default)]
+    pub properties: Vec<Property>,
+    #[serde(flatten)]
+    pub kind: LayerKind,
+}
+
+/// Tiled's layer types, by the name of their `type` field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LayerKind {
+    TileLayer(TileLayer),
+    ObjectGroup(ObjectGroup),
+    ImageLayer(ImageLayer),
+    Group(GroupLayer),
+}
+
+/// Always decoded, whatever the encoding and compression of the file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RawTileLayer", into = "RawTileLayer")]
+pub struct TileLayer {
+    /// Don't overlap, infinite maps only have chunks where tiles were painted
+    pub chunks: Vec<Chunk>,
+    /// None unless the chunks are the same size and aligned to it, as Tiled cuts them
+    index: Option<ChunkIndex>,
+}
+
+/// Where to find the chunk covering a tile, without looking at every chunk
+#[derive(Debug, Clone, PartialEq)]
+struct ChunkIndex {
+    width: i32,
+    height: i32,
+    /// Index in `TileLayer::chunks` by column and row of chunks
+    chunks: HashMap<(i32, i32), usize>,
+}
+
+/// A tile layer as Tiled writes it, with `data` in finite maps and `chunks` in infinite ones,
+/// their format depends on the `encoding`
+#[derive(Serialize, Deserialize)]
+struct RawTileLayer {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<RawData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chunks: Option<Vec<RawChunk>>,
+    #[serde(default)]
+    encoding: Encoding,
+    #[serde(default)]
+    compression: Compression,
+    height: i32,
+    width: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawChunk {
+    data: RawData,
+    height: i32,
+    width: i32,
+    x: i32,
+    y: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawData {
+    Tiles(Vec<TileRef>),
+    Encoded(String),
+}
+
+impl TryFrom<RawTileLayer> for TileLayer {
+    type Error = String;
+
+    fn try_from(raw: RawTileLayer) -> Result<Self, Self::Error> {
+        let (encoding, compression) = (raw.encoding, raw.compression);
+        let decode = |data: RawData| match (encoding, data) {
+            (Encoding::Csv, RawData::Tiles(tiles)) => Ok(tiles),
+            (Encoding::Base64, RawData::Encoded(data)) => decode_base64(&data, compression),
+            (encoding, _) => Err(format!("layer data doesn't match {:?}", encoding)),
+        };
+
+        let chunks = match (raw.data, raw.chunks) {
+            (Some(data), None) => {
+                let bounds = Bounds {
+                    x: 0,
+                    y: 0,
+                    width: raw.width,
+                    height: raw.height,
+                };
+                vec![Chunk::new(bounds, decode(data)?)?]
+            }
+            (None, Some(chunks)) => chunks
+                .into_iter()
+                .map(|chunk| {
+                    let bounds = Bounds {
+                        x: chunk.x,
+                        y: chunk.y,
+                        width: chunk.width,
+                        height: chunk.height,
+                    };
+                    Chunk::new(bounds, decode(chunk.data)?)
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err("tile layers have either data or chunks".to_string()),
+        };
+
+        Ok(TileLayer::new(chunks))
+    }
+}
+
+impl From<TileLayer> for RawTileLayer {
+    fn from(layer: TileLayer) -> Self {
+        let bounds = layer.bounds().unwrap_or(Bounds {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        });
+        let finite = layer.chunks.len() == 1 && bounds.x == 0 && bounds.y == 0;
+
+        let (data, chunks) = if finite {
+            let tiles = layer
+                .chunks
+                .into_iter()
+                .flat_map(|chunk| chunk.data)
+                .collect();
+            (Some(RawData::Tiles(tiles)), None)
+        } else {
+            let chunks = layer
+                .chunks
+                .into_iter()
+                .map(|chunk| RawChunk {
+                    data: RawData::Tiles(chunk.data),
+                    height: chunk.height,
+                    width: chunk.width,
+                    x: chunk.x,
+                    y: chunk.y,
+                })
+                .collect();
+            (None, Some(chunks))
+        };
+
+        RawTileLayer {
+            data,
+            chunks,
+            encoding: Encoding::Csv,
+            compression: Compression::None,
+            height: bounds.height,
+            width: bounds.width,
+        }
+    }
+}
+
+/// A cell of a tile layer, Tiled stores the flips in the high bits of the global tile id
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "u32", into = "u32")]
+pub struct TileRef {
+    /// 0 for empty cells
+    pub gid: i32,
+    pub flip_h: bool,
+    pub flip_v: bool,
+    /// Swaps the x and y axes, applied before the other flips
+    pub flip_d: bool,
+}
+
+impl TileRef {
+    pub fn is_empty(&self) -> bool {
+        self.gid == 0
+    }
+}
+
+impl From<u32> for TileRef {
+    fn from(bits: u32) -> Self {
+        TileRef {
+            gid: (bits & !FLAGS) as i32,
+            flip_h: bits & FLIPPED_HORIZONTALLY != 0,
+            flip_v: bits & FLIPPED_VERTICALLY != 0,
+            flip_d: bits & FLIPPED_DIAGONALLY != 0,
+        }
+    }
+}
+
+impl From<TileRef> for u32 {
+    fn from(tile: TileRef) -> Self {
+        let mut bits = tile.gid as u32;
+        if tile.flip_h {
+            bits |= FLIPPED_HORIZONTALLY;
+        }
+        if tile.flip_v {
+            bits |= FLIPPED_VERTICALLY;
+        }
+        if tile.flip_d {
+            bits |= FLIPPED_DIAGONALLY;
+        }
+        bits
+    }
+}
+
+impl TileLayer {
+    pub(super) fn new(chunks: Vec<Chunk>) -> TileLayer {
+        let index = chunks.first().and_then(|first| {
+            let (width, height) = (first.width, first.height);
+            if width <= 0 || height <= 0 {
+                return None;
+            }
+
+            let chunks = chunks
+                .iter()
+                .enumerate()
+                .map(|(index, chunk)| {
+                    let aligned = chunk.width == width
+                        && chunk.height == height
+                        && chunk.x.rem_euclid(width) == 0
+                        && chunk.y.rem_euclid(height) == 0;
+                    let position = (chunk.x.div_euclid(width), chunk.y.div_euclid(height));
+                    if aligned {
+                        Some((position, index))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Option<_>>()?;
+
+            Some(ChunkIndex {
+                width,
+                height,
+                chunks,
+            })
+        });
+
+        TileLayer { chunks, index }
+    }
+
+    /// Covers every chunk, None without any
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.chunks
+            .iter()
+            .map(Chunk::bounds)
+            .fold(None, |bounds: Option<Bounds>, chunk| match bounds {
+                Some(bounds) => Some(bounds.union(&chunk)),
+                None => Some(chunk),
+            })
+    }
+
+    /// `x`, `y` in tiles from the top left corner of the map, empty outside of the chunks
+    pub fn tile(&self, x: i32, y: i32) -> TileRef {
+        let chunk = match &self.index {
+            Some(index) => index
+                .chunks
+                .get(&(x.div_euclid(index.width), y.div_euclid(index.height)))
+                .map(|&chunk| &self.chunks[chunk]),
+            None => self
+                .chunks
+                .iter()
+                .find(|chunk| chunk.bounds().contains(x, y)),
+        };
+
+        chunk.map_or_else(TileRef::default, |chunk| chunk.tile(x, y))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectGroup {
+    pub objects: Vec<Object>,
+}
+
+/// A single image, drawn at the layer offset
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageLayer {
+    pub image: String,
+}
+
+/// Layers nested in a folder
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupLayer {
+    pub layers: Vec<Layer>,
+}
+
+/// A layer as it's shown, the groups it's nested in hide and move it too
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NestedLayer<'a> {
+    pub layer: &'a Layer,
+    /// Hidden along with any of its groups
+    pub visible: bool,
+    /// Its own offset plus the ones of its groups, in pixels
+    pub offset_x: f32,
+    pub offset_y: f32,
+}
+
+impl CustomProperties for Layer {
+    fn properties(&self) -> &[Property] {
+        &self.properties
+    }
+}
+
+impl Layer {
+    /// This layer followed by the ones nested in it, depth first
+    pub fn flatten(&self) -> Vec<NestedLayer> {
+        let mut layers = vec![];
+        self.flatten_into(true, 0.0, 0.0, &mut layers);
+        layers
+    }
+
+    fn flatten_into<'a>(
+        &'a self,
+        parent_visible: bool,
+        parent_offset_x: f32,
+        parent_offset_y: f32,
+        layers: &mut Vec<NestedLayer<'a>>,
+    ) {
+        let nested = NestedLayer {
+            layer: self,
+            visible: parent_visible && self.visible,
+            offset_x: parent_offset_x + self.offset_x,
+            offset_y: parent_offset_y + self.offset_y,
+        };
+        layers.push(nested);
+
+        if let LayerKind::Group(group) = &self.kind {
+            for layer in group.layers.iter() {
+                layer.flatten_into(nested.visible, nested.offset_x, nested.offset_y, layers);
+            }
+        }
+    }
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiled::Map;
+
+    #[test]
+    fn test_tile_flips() {
+        let data = r#"{
+            "data": [0, 5, 2147483653, 1073741829, 3758096389], "width": 5, "height": 1
+        }"#;
+        let layer: TileLayer = serde_json::from_str(data).expect("Failed to parse layer");
+
+        assert!(layer.tile(0, 0).is_empty());
+        assert!(layer.chunks[0].data[1..].iter().all(|tile| tile.gid == 5));
+
+        let flips: Vec<(bool, bool, bool)> = layer.chunks[0]
+            .data
+            .iter()
+            .map(|tile| (tile.flip_h, tile.flip_v, tile.flip_d))
+            .collect();
+        assert_eq!(
+            vec![
+                (false, false, false),
+                (false, false, false),
+                (true, false, false),
+                (false, true, false),
+                (true, true, true),
+            ],
+            flips
+        );
+
+        let bits: Vec<u32> = layer.chunks[0]
+            .data
+            .iter()
+            .copied()
+            .map(u32::from)
+            .collect();
+        assert_eq!(vec![0, 5, 2147483653, 1073741829, 3758096389], bits);
+    }
+
+    #[test]
+    fn test_encoded_layer_data() {
+        let csv = Map::from_file("assets/test_maps/csv.json").expect("Failed to load map");
+        let expected = &csv.tile_layers()[0].1.chunks[0].data;
+        assert_eq!(16, expected.len());
+        assert!(expected[7].flip_h && expected[13].flip_v);
+
+        for variant in ["base64", "zlib", "gzip", "zstd"].iter() {
+            let path = format!("assets/test_maps/{}.json", variant);
+            let map = Map::from_file(&path).expect("Failed to load map");
+            assert_eq!(
+                expected,
+                &map.tile_layers()[0].1.chunks[0].data,
+                "{}",
+                variant
+            );
+        }
+
+        let data = r#"{"data": "AQAAAA==", "encoding": "base64", "width": 2, "height": 1}"#;
+        assert!(serde_json::from_str::<TileLayer>(data).is_err());
+    }
+
+    #[test]
+    fn test_unaligned_chunks() {
+        // Chunks that aren't cut like Tiled does are still found
+        let data = r#"{"height": 0, "width": 0, "type": "tilelayer", "chunks": [
+            {"x": -1, "y": 0, "width": 1, "height": 2, "data": [1, 2]},
+            {"x": 0, "y": 0, "width": 2, "height": 1, "data": [3, 4]}
+        ]}"#;
+        let layer: TileLayer = serde_json::from_str(data).expect("Failed to parse layer");
+        assert_eq!(2, layer.tile(-1, 1).gid);
+        assert_eq!(4, layer.tile(1, 0).gid);
+        assert!(layer.tile(0, 1).is_empty());
+    }
+
+    #[test]
+    fn test_layer_kinds() {
+        let data = r#"{
+            "height": 1, "width": 2, "tileheight": 32, "tilewidth": 32, "tilesets": [],
+            "layers": [
+                {"id": 1, "name": "ground", "type": "tilelayer", "data": [1, 2],
+                 "width": 2, "height": 1},
+                {"id": 2, "name": "sky", "type": "imagelayer", "image": "sky.png",
+                 "opacity": 0.5},
+                {"id": 3, "name": "folder", "type": "group", "visible": false, "layers": [
+                    {"id": 4, "name": "things", "type": "objectgroup", "objects": [
+                        {"id": 1, "x": 10, "y": 20, "point": true}
+                    ]}
+                ]}
+            ]
+        }"#;
+        let map: Map = serde_json::from_str(data).expect("Failed to parse map");
+
+        let layers = map.all_layers();
+        assert_eq!(4, layers.len());
+        match &layers[1].layer.kind {
+            LayerKind::ImageLayer(image) => assert_eq!("sky.png", image.image),
+            kind => panic!("Unexpected layer {:?}", kind),
+        }
+        assert_eq!(0.5, layers[1].layer.opacity);
+        assert!(!layers[2].visible);
+        assert!(!layers[3].visible);
+        assert_eq!(1, map.objects().len());
+
+        let ground = map.tile_layers()[0].1;
+        let gids: Vec<i32> = ground.chunks[0].data.iter().map(|tile| tile.gid).collect();
+        assert_eq!(vec![1, 2], gids);
+    }
+
+    #[test]
+    fn test_nested_layers() {
+        let data = r#"{
+            "height": 1, "width": 1, "tileheight": 32, "tilewidth": 32, "tilesets": [],
+            "layers": [
+                {"id": 1, "name": "outer", "type": "group", "offsetx": 8, "offsety": 4,
+                 "layers": [
+                    {"id": 2, "name": "inner", "type": "group", "visible": false,
+                     "offsety": 2, "layers": [
+                        {"id": 3, "name": "ground", "type": "tilelayer", "data": [1],
+                         "width": 1, "height": 1, "offsetx": 1}
+                    ]}
+                ]},
+                {"id": 4, "name": "top", "type": "tilelayer", "data": [1],
+                 "width": 1, "height": 1}
+            ]
+        }"#;
+        let map: Map = serde_json::from_str(data).expect("Failed to parse map");
+
+        let layers = map.tile_layers();
+        assert_eq!(2, layers.len());
+        let (ground, _) = layers[0];
+        assert_eq!(3, ground.layer.id);
+        assert!(ground.layer.visible);
+        // Hidden by its group
+        assert!(!ground.visible);
+        assert_eq!((9.0, 6.0), (ground.offset_x, ground.offset_y));
+
+        let (top, _) = layers[1];
+        assert!(top.visible);
+        assert_eq!((0.0, 0.0), (top.offset_x, top.offset_y));
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::Deserialize;
+
+use super::{tmx, Layer, Map, MapError, Property, TileSet};
+
+/// Loads maps, their external tilesets are only read again once their file changed
+#[derive(Debug, Default)]
+pub struct MapLoader {
+    /// By path, images are still relative to the tileset file
+    tilesets: HashMap<PathBuf, CachedTileSet>,
+}
+
+#[derive(Debug)]
+struct CachedTileSet {
+    /// Modification time of the file when it was read
+    modified: Option<SystemTime>,
+    tileset: TileSet,
+}
+
+/// A map as Tiled writes it in JSON, its tilesets can be stored in files of their own
+#[derive(Deserialize)]
+struct JsonMap {
+    height: i32,
+    width: i32,
+    layers: Vec<Layer>,
+    tilesets: Vec<TileSetEntry>,
+    #[serde(rename = "tileheight")]
+    tile_height: i32,
+    #[serde(rename = "tilewidth")]
+    tile_width: i32,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TileSetEntry {
+    External {
+        #[serde(rename = "firstgid")]
+        first_gid: i32,
+        source: String,
+    },
+    Embedded(TileSet),
+}
+
+impl MapLoader {
+    /// Reads a `.tmx` file or a JSON export, depending on the extension of `path`
+    pub fn load(&mut self, path: &str) -> Result<Map, MapError> {
+        let data = std::fs::read_to_string(path)?;
+        self.parse(&data, Path::new(path))
+    }
+
+    /// Parses `data`, the contents of the file at `path` which external tilesets are relative to
+    pub fn parse(&mut self, data: &str, path: &Path) -> Result<Map, MapError> {
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+        if has_extension(path, "tmx") {
+            tmx::parse_map(data, directory, self)
+        } else {
+            self.parse_json(data, directory)
+        }
+    }
+
+    fn parse_json(&mut self, data: &str, directory: &Path) -> Result<Map, MapError> {
+        let map: JsonMap = serde_json::from_str(data)?;
+
+        let tilesets = map
+            .tilesets
+            .into_iter()
+            .map(|entry| match entry {
+                TileSetEntry::External { first_gid, source } => {
+                    self.external_tileset(directory, &source, first_gid)
+                }
+                TileSetEntry::Embedded(tileset) => Ok(tileset),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Map {
+            height: map.height,
+            width: map.width,
+            layers: map.layers,
+            tilesets,
+            tile_height: map.tile_height,
+            tile_width: map.tile_width,
+            properties: map.properties,
+        })
+    }
+
+    /// The tileset stored in `source`, a `.tsx`, `.tsj` or `.json` file relative to `directory`
+    pub(super) fn external_tileset(
+        &mut self,
+        directory: &Path,
+        source: &str,
+        first_gid: i32,
+    ) -> Result<TileSet, MapError> {
+        let path = directory.join(source);
+        let modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        let cached = self.tilesets.get(&path);
+        if cached.map_or(true, |cached| cached.modified != modified) {
+            let tileset = read_tileset(&path).map_err(|error| MapError::TileSet {
+                path: path.clone(),
+                error: Box::new(error),
+            })?;
+            self.tilesets
+                .insert(path.clone(), CachedTileSet { modified, tileset });
+        }
+
+        let mut tileset = self.tilesets[&path].tileset.clone();
+        tileset.first_gid = first_gid;
+        // The map wants its images relative to itself
+        if let Some(tileset_directory) = Path::new(source).parent() {
+            tileset.image = tileset_directory
+                .join(&tileset.image)
+                .to_string_lossy()
+                .into_owned();
+        }
+
+        Ok(tileset)
+    }
+}
+
+fn read_tileset(path: &Path) -> Result<TileSet, MapError> {
+    let data = std::fs::read_to_string(path)?;
+
+    if has_extension(path, "tsx") {
+        tmx::parse_tileset_file(&data)
+    } else {
+        Ok(serde_json::from_str(&data)?)
+    }
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().map_or(false, |e| e == extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_external_tilesets() {
+        let mut loader = MapLoader::default();
+        let map = loader
+            .load("assets/test_maps/external.json")
+            .expect("Failed to load map");
+
+        assert_eq!(1, map.tilesets[0].first_gid);
+        assert_eq!("tilesets/../../tileset.png", map.tilesets[0].image);
+        assert_eq!(Some((0, 3)), map.resolve_gid(4));
+        assert_eq!(181, map.tilesets[1].first_gid);
+        assert_eq!("../tileset.png", map.tilesets[1].image);
+        assert_eq!(Some((1, 0)), map.resolve_gid(181));
+
+        // The tmx map shares its tileset with the json one
+        let tmx = loader
+            .load("assets/test_maps/map.tmx")
+            .expect("Failed to load map");
+        assert_eq!(map.tilesets[1].tiles, tmx.tilesets[0].tiles);
+        assert_eq!(2, loader.tilesets.len());
+    }
+
+    #[test]
+    fn test_parse_relative_to_path() {
+        // Assets are read by the asset server, the loader only gets their contents
+        let data = std::fs::read_to_string("assets/test_maps/map.tmx").expect("Failed to read");
+        let mut loader = MapLoader::default();
+        let map = loader
+            .parse(&data, Path::new("assets/test_maps/map.tmx"))
+            .expect("Failed to parse map");
+
+        let json = loader
+            .load("assets/test_maps/map.json")
+            .expect("Failed to load map");
+        assert_eq!(json, map);
+    }
+
+    #[test]
+    fn test_changed_tileset_is_read_again() {
+        let mut loader = MapLoader::default();
+        let map = loader
+            .load("assets/test_maps/external.json")
+            .expect("Failed to load map");
+
+        // As if the file was written since
+        for cached in loader.tilesets.values_mut() {
+            cached.modified = Some(SystemTime::UNIX_EPOCH);
+            cached.tileset.tile_width = 0;
+        }
+        let reloaded = loader
+            .load("assets/test_maps/external.json")
+            .expect("Failed to load map");
+        assert_eq!(map, reloaded);
+    }
+
+    #[test]
+    fn test_missing_tileset() {
+        let data = r#"{
+            "height": 1, "width": 1, "tileheight": 32, "tilewidth": 32, "layers": [],
+            "tilesets": [{"firstgid": 1, "source": "missing.tsj"}]
+        }"#;
+        let result = MapLoader::default().parse_json(data, Path::new("assets/test_maps"));
+
+        match result {
+            Err(MapError::TileSet { path, error }) => {
+                assert_eq!(Path::new("assets/test_maps/missing.tsj"), path);
+                assert!(matches!(*error, MapError::Io(_)));
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+}
@@ -1,7 +1,31 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
 use serde::{Deserialize, Serialize};
-use serde_json::Result;
 
-#[derive(Debug, Serialize, Deserialize)]
+mod asset;
+mod chunk;
+mod encoding;
+mod layer;
+mod loader;
+mod object;
+mod property;
+mod tmx;
+
+pub use self::asset::TiledMapLoader;
+pub use self::chunk::{Bounds, Chunk};
+pub use self::layer::{
+    GroupLayer, ImageLayer, Layer, LayerKind, NestedLayer, ObjectGroup, TileLayer, TileRef,
+};
+pub use self::loader::MapLoader;
+pub use self::object::{Object, Shape, Vertex};
+pub use self::property::{ClassValue, CustomProperties, Property, PropertyValue};
+
+/// Empty by default, the game starts on one while the asset server loads the real map
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, TypeUuid)]
+#[uuid = "6d898ea9-1f19-45b5-a530-f602713b2ecb"]
 pub struct Map {
     pub height: i32,
     pub width: i32,
@@ -11,19 +35,16 @@ pub struct Map {
     pub tile_height: i32,
     #[serde(rename = "tilewidth")]
     pub tile_width: i32,
+    #[serde(default)]
+    pub properties: Vec<Property>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Layer {
-    pub data: Vec<i32>,
-    pub height: i32,
-    pub width: i32,
-    pub id: i32,
-    pub name: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TileSet {
+    /// Global id of the first tile, the tiles of every tileset share the same ids
+    /// Tileset files leave it out, it depends on the map using them.
+    #[serde(rename = "firstgid", default)]
+    pub first_gid: i32,
     pub columns: i32,
     pub image: String,
     #[serde(rename = "imageheight")]
@@ -37,36 +58,224 @@ pub struct TileSet {
     pub tile_height: i32,
     #[serde(rename = "tilewidth")]
     pub tile_width: i32,
+    /// Only the tiles with properties or an animation are listed
+    #[serde(default)]
     pub tiles: Vec<Tile>,
+    #[serde(default)]
+    pub properties: Vec<Property>,
+}
+
+impl TileSet {
+    /// `id` is local to this tileset
+    pub fn tile(&self, id: i32) -> Option<&Tile> {
+        self.tiles.iter().find(|tile| tile.id == id)
+    }
+
+    pub fn rows(&self) -> i32 {
+        self.tile_count / self.columns
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Tile {
     pub id: i32,
-    pub properties: Vec<TileProperty>,
+    #[serde(default)]
+    pub properties: Vec<Property>,
+    /// Empty unless the tile is animated
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub animation: Vec<Frame>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct TileProperty {
-    pub name: String,
-    #[serde(rename = "type")]
-    pub property_type: String,
-    pub value: PropertyValue,
+/// Animated tiles show other tiles of their tileset in turn, looping
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    #[serde(rename = "tileid")]
+    pub tile_id: i32,
+    /// In milliseconds
+    pub duration: u32,
+}
+
+impl CustomProperties for Map {
+    fn properties(&self) -> &[Property] {
+        &self.properties
+    }
+}
+
+impl CustomProperties for TileSet {
+    fn properties(&self) -> &[Property] {
+        &self.properties
+    }
+}
+
+impl CustomProperties for Tile {
+    fn properties(&self) -> &[Property] {
+        &self.properties
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Xml(String),
+    /// An external tileset of the map couldn't be loaded
+    TileSet {
+        path: PathBuf,
+        error: Box<MapError>,
+    },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Io(e) => write!(f, "map io error: {}", e),
+            MapError::Json(e) => write!(f, "invalid map: {}", e),
+            MapError::Xml(e) => write!(f, "invalid tmx map: {}", e),
+            MapError::TileSet { path, error } => {
+                write!(f, "couldn't load tileset {}: {}", path.display(), error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+impl From<std::io::Error> for MapError {
+    fn from(e: std::io::Error) -> Self {
+        MapError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for MapError {
+    fn from(e: serde_json::Error) -> Self {
+        MapError::Json(e)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(untagged)]
-pub enum PropertyValue {
-    Bool(bool),
-    String(String),
+impl From<roxmltree::Error> for MapError {
+    fn from(e: roxmltree::Error) -> Self {
+        MapError::Xml(e.to_string())
+    }
 }
 
 impl Map {
-    pub fn from_json_file(path: &str) -> Result<Map> {
-        let data = std::fs::read_to_string(path).expect("Unable to read path file");
-        let map: Map = serde_json::from_str(data.as_str())?;
-        Ok(map)
+    /// Reads a `.tmx` file or a JSON export, depending on the extension of `path`
+    /// Use a `MapLoader` to share the external tilesets of several maps.
+    pub fn from_file(path: &str) -> Result<Map, MapError> {
+        MapLoader::default().load(path)
     }
+
+    /// Index of the tileset the global tile id `gid` belongs to, with the tile id in that tileset
+    /// None for 0, which stands for no tile.
+    pub fn resolve_gid(&self, gid: i32) -> Option<(usize, i32)> {
+        self.tilesets
+            .iter()
+            .enumerate()
+            .filter(|(_, tileset)| gid >= tileset.first_gid && gid > 0)
+            .max_by_key(|(_, tileset)| tileset.first_gid)
+            .map(|(index, tileset)| (index, gid - tileset.first_gid))
+    }
+
+    /// Every layer, the ones in groups included, depth first
+    pub fn all_layers(&self) -> Vec<NestedLayer> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.flatten())
+            .collect()
+    }
+
+    /// Tile layers, along with the layer fields they have in common with the others
+    pub fn tile_layers(&self) -> Vec<(NestedLayer, &TileLayer)> {
+        self.all_layers()
+            .into_iter()
+            .filter_map(|layer| match &layer.layer.kind {
+                LayerKind::TileLayer(tiles) => Some((layer, tiles)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Objects of every object group, in layer order
+    pub fn objects(&self) -> Vec<&Object> {
+        self.all_layers()
+            .into_iter()
+            .filter_map(|layer| match &layer.layer.kind {
+                LayerKind::ObjectGroup(group) => Some(group.objects.iter()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Tiles covered by the tile layers, the size of the map unless it's infinite
+    pub fn bounds(&self) -> Bounds {
+        self.tile_layers()
+            .into_iter()
+            .filter_map(|(_, tiles)| tiles.bounds())
+            .fold(None, |bounds: Option<Bounds>, layer| match bounds {
+                Some(bounds) => Some(bounds.union(&layer)),
+                None => Some(layer),
+            })
+            .unwrap_or(Bounds {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            })
+    }
+
+    /// Converts positions on this map, keep it around to convert many of them
+    pub fn projection(&self) -> MapProjection {
+        let bounds = self.bounds();
+        let left = (bounds.x * self.tile_width) as f32;
+        let top = (bounds.y * self.tile_height) as f32;
+        let map_width = (bounds.width * self.tile_width) as f32;
+        let map_height = (bounds.height * self.tile_height) as f32;
+
+        MapProjection {
+            origin: Vec2::new(-left - map_width / 2.0, top + map_height / 2.0),
+            tile_size: Vec2::new(self.tile_width as f32, self.tile_height as f32),
+        }
+    }
+
+    /// See `MapProjection::world_position`
+    pub fn world_position(&self, x: f32, y: f32) -> Vec2 {
+        self.projection().world_position(x, y)
+    }
+
+    /// See `MapProjection::tile_position`
+    pub fn tile_position(&self, position: Vec2) -> (i32, i32) {
+        self.projection().tile_position(position)
+    }
+}
+
+/// Between the pixels of a map and the world, the map `bounds` are only computed once
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MapProjection {
+    /// World position of the top left corner of the tile at 0, 0
+    origin: Vec2,
+    tile_size: Vec2,
+}
+
+impl MapProjection {
+    /// World position of the pixel at `x`, `y` from the top left corner,
+    /// the world origin is at the center of the map `bounds` and its y axis points up
+    pub fn world_position(&self, x: f32, y: f32) -> Vec2 {
+        self.origin + Vec2::new(x, -y)
+    }
+
+    /// Tile under the world `position`, which may be out of the map
+    pub fn tile_position(&self, position: Vec2) -> (i32, i32) {
+        (
+            ((position.x - self.origin.x) / self.tile_size.x).floor() as i32,
+            ((self.origin.y - position.y) / self.tile_size.y).floor() as i32,
+        )
+    }
+}
+
+/// Layers and objects are visible unless Tiled says otherwise
+fn default_visible() -> bool {
+    true
 }
 
 #[cfg(test)]
@@ -75,7 +284,71 @@ mod tests {
 
     #[test]
     fn test_load_json_file() {
-        let map = Map::from_json_file("assets/basic_map.json").expect("Failed to load map");
+        let map = Map::from_file("assets/basic_map.tmj").expect("Failed to load map");
         assert_eq!(50, map.height);
     }
+
+    #[test]
+    fn test_objects() {
+        let map = Map::from_file("assets/basic_map.tmj").expect("Failed to load map");
+        assert_eq!(2, map.tile_layers().len());
+
+        let object = map.objects()[0];
+        assert_eq!("unit", object.object_type);
+        assert_eq!(Shape::Point, object.shape);
+        assert_eq!(Some(&PropertyValue::Int(1)), object.property("owner"));
+
+        let (x, y) = object.center();
+        assert_eq!(Vec2::new(100.0, 100.0), map.world_position(x, y));
+    }
+
+    #[test]
+    fn test_resolve_gid() {
+        let data = r#"{
+            "height": 1, "width": 1, "tileheight": 32, "tilewidth": 32, "layers": [],
+            "tilesets": [
+                {"firstgid": 1, "columns": 2, "image": "a.png", "imageheight": 64,
+                 "imagewidth": 64, "name": "a", "tilecount": 4, "tileheight": 32,
+                 "tilewidth": 32},
+                {"firstgid": 5, "columns": 1, "image": "b.png", "imageheight": 64,
+                 "imagewidth": 32, "name": "b", "tilecount": 2, "tileheight": 32,
+                 "tilewidth": 32, "tiles": [{"id": 1, "properties": [
+                    {"name": "walkable", "type": "bool", "value": false}]}]}
+            ]
+        }"#;
+        let map: Map = serde_json::from_str(data).expect("Failed to parse map");
+
+        assert_eq!(None, map.resolve_gid(0));
+        assert_eq!(Some((0, 0)), map.resolve_gid(1));
+        assert_eq!(Some((0, 3)), map.resolve_gid(4));
+        assert_eq!(Some((1, 1)), map.resolve_gid(6));
+
+        let tile = map.tilesets[1].tile(1).expect("Missing tile");
+        assert_eq!(Some(&PropertyValue::Bool(false)), tile.property("walkable"));
+    }
+
+    #[test]
+    fn test_infinite_map() {
+        let map = Map::from_file("assets/test_maps/infinite.json").expect("Failed to load map");
+
+        let bounds = Bounds {
+            x: -4,
+            y: -4,
+            width: 8,
+            height: 8,
+        };
+        assert_eq!(bounds, map.bounds());
+
+        let ground = map.tile_layers()[0].1;
+        assert_eq!(2, ground.chunks.len());
+        assert_eq!(2, ground.tile(-3, -3).gid);
+        assert_eq!(3, ground.tile(2, 2).gid);
+        assert!(ground.tile(-1, 2).is_empty());
+        assert!(ground.tile(10, 10).is_empty());
+
+        // The world origin is at the center of the painted tiles
+        assert_eq!(Vec2::new(-128.0, 128.0), map.world_position(-128.0, -128.0));
+        assert_eq!((-4, -4), map.tile_position(Vec2::new(-120.0, 120.0)));
+        assert_eq!((3, 3), map.tile_position(Vec2::new(120.0, -120.0)));
+    }
 }
@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+
+use super::{default_visible, CustomProperties, Property, TileRef};
+
+/// Placed on the map in an object group, positions are in pixels from the top left corner
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "RawObject", into = "RawObject")]
+pub struct Object {
+    pub id: i32,
+    pub name: String,
+    /// Called class since Tiled 1.9
+    pub object_type: String,
+    pub x: f32,
+    pub y: f32,
+    /// Degrees, clockwise around `x`, `y`
+    pub rotation: f32,
+    /// Tile objects show this tile, they are placed by their bottom left corner
+    pub gid: Option<TileRef>,
+    pub visible: bool,
+    pub shape: Shape,
+    pub properties: Vec<Property>,
+}
+
+/// Object shapes, sizes and points are relative to the object position
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Rectangle { width: f32, height: f32 },
+    Ellipse { width: f32, height: f32 },
+    Point,
+    Polygon(Vec<Vertex>),
+    Polyline(Vec<Vertex>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Vertex {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl CustomProperties for Object {
+    fn properties(&self) -> &[Property] {
+        &self.properties
+    }
+}
+
+impl Object {
+    /// Middle of the shape in pixels from the top left corner of the map, rotation included
+    pub fn center(&self) -> (f32, f32) {
+        let (offset_x, offset_y) = match &self.shape {
+            Shape::Rectangle { width, height } | Shape::Ellipse { width, height } => {
+                match self.gid {
+                    Some(_) => (width / 2.0, -height / 2.0),
+                    None => (width / 2.0, height / 2.0),
+                }
+            }
+            Shape::Point => (0.0, 0.0),
+            Shape::Polygon(vertices) | Shape::Polyline(vertices) if !vertices.is_empty() => {
+                let count = vertices.len() as f32;
+                (
+                    vertices.iter().map(|vertex| vertex.x).sum::<f32>() / count,
+                    vertices.iter().map(|vertex| vertex.y).sum::<f32>() / count,
+                )
+            }
+            Shape::Polygon(_) | Shape::Polyline(_) => (0.0, 0.0),
+        };
+
+        // The y axis points down, positive angles turn clockwise
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        (
+            self.x + offset_x * cos - offset_y * sin,
+            self.y + offset_x * sin + offset_y * cos,
+        )
+    }
+}
+
+/// An object as Tiled writes it, with a flag or a field per shape
+#[derive(Clone, Serialize, Deserialize)]
+struct RawObject {
+    id: i32,
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type", alias = "class", default)]
+    object_type: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gid: Option<TileRef>,
+    #[serde(default = "default_visible")]
+    visible: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    point: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    ellipse: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    polygon: Option<Vec<Vertex>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    polyline: Option<Vec<Vertex>>,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+impl From<RawObject> for Object {
+    fn from(raw: RawObject) -> Self {
+        let shape = if raw.point {
+            Shape::Point
+        } else if raw.ellipse {
+            Shape::Ellipse {
+                width: raw.width,
+                height: raw.height,
+            }
+        } else if let Some(vertices) = raw.polygon {
+            Shape::Polygon(vertices)
+        } else if let Some(vertices) = raw.polyline {
+            Shape::Polyline(vertices)
+        } else {
+            Shape::Rectangle {
+                width: raw.width,
+                height: raw.height,
+            }
+        };
+
+        Object {
+            id: raw.id,
+            name: raw.name,
+            object_type: raw.object_type,
+            x: raw.x,
+            y: raw.y,
+            rotation: raw.rotation,
+            gid: raw.gid,
+            visible: raw.visible,
+            shape,
+            properties: raw.properties,
+        }
+    }
+}
+
+impl From<Object> for RawObject {
+    fn from(object: Object) -> Self {
+        let mut raw = RawObject {
+            id: object.id,
+            name: object.name,
+            object_type: object.object_type,
+            x: object.x,
+            y: object.y,
+            width: 0.0,
+            height: 0.0,
+            rotation: object.rotation,
+            gid: object.gid,
+            visible: object.visible,
+            point: false,
+            ellipse: false,
+            polygon: None,
+            polyline: None,
+            properties: object.properties,
+        };
+
+        match object.shape {
+            Shape::Rectangle { width, height } => {
+                raw.width = width;
+                raw.height = height;
+            }
+            Shape::Ellipse { width, height } => {
+                raw.width = width;
+                raw.height = height;
+                raw.ellipse = true;
+            }
+            Shape::Point => raw.point = true,
+            Shape::Polygon(vertices) => raw.polygon = Some(vertices),
+            Shape::Polyline(vertices) => raw.polyline = Some(vertices),
+        }
+
+        raw
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiled::PropertyValue;
+
+    #[test]
+    fn test_object_shapes() {
+        let data = r#"[
+            {"id": 1, "x": 10, "y": 20, "width": 4, "height": 6},
+            {"id": 2, "x": 10, "y": 20, "width": 4, "height": 6, "ellipse": true},
+            {"id": 3, "x": 10, "y": 20, "point": true, "class": "spawn"},
+            {"id": 4, "x": 10, "y": 20, "polygon": [
+                {"x": 0, "y": 0}, {"x": 4, "y": 0}, {"x": 4, "y": 6}]},
+            {"id": 5, "x": 10, "y": 20, "polyline": [{"x": 0, "y": 0}, {"x": 8, "y": 0}]},
+            {"id": 6, "x": 10, "y": 20, "width": 4, "height": 6, "rotation": 90,
+             "gid": 2147483655,
+             "properties": [{"name": "hp", "type": "float", "value": 1.5}]}
+        ]"#;
+        let objects: Vec<Object> = serde_json::from_str(data).expect("Failed to parse objects");
+
+        let shapes: Vec<&Shape> = objects.iter().map(|object| &object.shape).collect();
+        assert_eq!(
+            vec![
+                &Shape::Rectangle {
+                    width: 4.0,
+                    height: 6.0
+                },
+                &Shape::Ellipse {
+                    width: 4.0,
+                    height: 6.0
+                },
+                &Shape::Point,
+                &Shape::Polygon(vec![
+                    Vertex { x: 0.0, y: 0.0 },
+                    Vertex { x: 4.0, y: 0.0 },
+                    Vertex { x: 4.0, y: 6.0 },
+                ]),
+                &Shape::Polyline(vec![Vertex { x: 0.0, y: 0.0 }, Vertex { x: 8.0, y: 0.0 }]),
+                &Shape::Rectangle {
+                    width: 4.0,
+                    height: 6.0
+                },
+            ],
+            shapes
+        );
+
+        assert_eq!("spawn", objects[2].object_type);
+        assert_eq!((12.0, 23.0), objects[0].center());
+        let tile = objects[5].gid.expect("Missing gid");
+        assert_eq!((7, true, false), (tile.gid, tile.flip_h, tile.flip_v));
+        assert_eq!(Some(&PropertyValue::Float(1.5)), objects[5].property("hp"));
+
+        // Tile objects hang from their bottom left corner, turned a quarter clockwise
+        let (x, y) = objects[5].center();
+        assert!(
+            (x - 13.0).abs() < 0.001 && (y - 22.0).abs() < 0.001,
+            "{} {}",
+            x,
+            y
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use std::convert::TryFrom;
+
+use bevy::prelude::Color;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A custom property, set in Tiled on maps, layers, tilesets, tiles and objects
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RawProperty", into = "RawProperty")]
+pub struct Property {
+    pub name: String,
+    pub value: PropertyValue,
+}
+
+/// Parsed according to the `type` Tiled saves along with the value
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// Transparent when left empty
+    Color(Color),
+    /// Relative to the map file
+    File(String),
+    /// Id of an object of the map, 0 for none
+    Object(i32),
+    Class(ClassValue),
+}
+
+/// A value of a custom class type, only the members set in Tiled are listed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassValue {
+    /// Name of the class, empty for nested classes of JSON maps
+    pub property_type: String,
+    /// Sorted by name
+    pub members: Vec<Property>,
+}
+
+/// Typed access to custom properties, None when missing or of another type
+pub trait CustomProperties {
+    fn properties(&self) -> &[Property];
+
+    fn property(&self, name: &str) -> Option<&PropertyValue> {
+        self.properties()
+            .iter()
+            .find(|property| property.name == name)
+            .map(|property| &property.value)
+    }
+
+    fn property_bool(&self, name: &str) -> Option<bool> {
+        match self.property(name) {
+            Some(PropertyValue::Bool(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn property_i32(&self, name: &str) -> Option<i32> {
+        match self.property(name) {
+            Some(PropertyValue::Int(value)) => Some(*value as i32),
+            _ => None,
+        }
+    }
+
+    /// Int properties are accepted too
+    fn property_f32(&self, name: &str) -> Option<f32> {
+        match self.property(name) {
+            Some(PropertyValue::Float(value)) => Some(*value as f32),
+            Some(PropertyValue::Int(value)) => Some(*value as f32),
+            _ => None,
+        }
+    }
+
+    fn property_str(&self, name: &str) -> Option<&str> {
+        match self.property(name) {
+            Some(PropertyValue::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn property_color(&self, name: &str) -> Option<Color> {
+        match self.property(name) {
+            Some(PropertyValue::Color(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn property_file(&self, name: &str) -> Option<&str> {
+        match self.property(name) {
+            Some(PropertyValue::File(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn property_object(&self, name: &str) -> Option<i32> {
+        match self.property(name) {
+            Some(PropertyValue::Object(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn property_class(&self, name: &str) -> Option<&ClassValue> {
+        match self.property(name) {
+            Some(PropertyValue::Class(value)) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl CustomProperties for ClassValue {
+    fn properties(&self) -> &[Property] {
+        &self.members
+    }
+}
+
+impl PropertyValue {
+    /// The value of a property of type `property_type` written as `value`, as in TMX files,
+    /// class values aren't written this way
+    pub fn parse(property_type: &str, value: &str) -> Result<PropertyValue, String> {
+        let invalid = || format!("invalid {} property {:?}", property_type, value);
+
+        Ok(match property_type {
+            "bool" => PropertyValue::Bool(value.parse().map_err(|_| invalid())?),
+            "int" => PropertyValue::Int(value.parse().map_err(|_| invalid())?),
+            "float" => PropertyValue::Float(value.parse().map_err(|_| invalid())?),
+            "string" => PropertyValue::String(value.to_string()),
+            "color" => PropertyValue::Color(parse_color(value).ok_or_else(invalid)?),
+            "file" => PropertyValue::File(value.to_string()),
+            "object" => PropertyValue::Object(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("unknown property type {}", property_type)),
+        })
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            PropertyValue::Bool(_) => "bool",
+            PropertyValue::Int(_) => "int",
+            PropertyValue::Float(_) => "float",
+            PropertyValue::String(_) => "string",
+            PropertyValue::Color(_) => "color",
+            PropertyValue::File(_) => "file",
+            PropertyValue::Object(_) => "object",
+            PropertyValue::Class(_) => "class",
+        }
+    }
+}
+
+/// `#AARRGGBB` or `#RRGGBB`
+fn parse_color(value: &str) -> Option<Color> {
+    if value.is_empty() {
+        return Some(Color::rgba_u8(0, 0, 0, 0));
+    }
+
+    let hex = value.strip_prefix('#')?;
+    let channel = |index: usize| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok();
+
+    match hex.len() {
+        8 => Some(Color::rgba_u8(
+            channel(2)?,
+            channel(4)?,
+            channel(6)?,
+            channel(0)?,
+        )),
+        6 => Some(Color::rgb_u8(channel(0)?, channel(2)?, channel(4)?)),
+        _ => None,
+    }
+}
+
+fn format_color(color: Color) -> String {
+    let channel = |value: f32| (value * 255.0).round() as u8;
+
+    format!(
+        "#{:02x}{:02x}{:02x}{:02x}",
+        channel(color.a()),
+        channel(color.r()),
+        channel(color.g()),
+        channel(color.b())
+    )
+}
+
+/// A property as Tiled writes it in JSON
+#[derive(Serialize, Deserialize)]
+struct RawProperty {
+    name: String,
+    #[serde(rename = "type", default = "default_type")]
+    property_type: String,
+    /// Name of the class of class properties
+    #[serde(
+        rename = "propertytype",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
+    class: String,
+    value: Value,
+}
+
+fn default_type() -> String {
+    "string".to_string()
+}
+
+impl TryFrom<RawProperty> for Property {
+    type Error = String;
+
+    fn try_from(raw: RawProperty) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid {} property {}", raw.property_type, raw.name);
+
+        let value = match (raw.property_type.as_str(), &raw.value) {
+            ("bool", Value::Bool(value)) => PropertyValue::Bool(*value),
+            ("int", Value::Number(value)) => {
+                PropertyValue::Int(value.as_i64().ok_or_else(invalid)?)
+            }
+            ("float", Value::Number(value)) => {
+                PropertyValue::Float(value.as_f64().ok_or_else(invalid)?)
+            }
+            ("object", Value::Number(value)) => {
+                PropertyValue::Object(value.as_i64().ok_or_else(invalid)? as i32)
+            }
+            ("class", Value::Object(members)) => PropertyValue::Class(ClassValue {
+                property_type: raw.class.clone(),
+                members: members
+                    .iter()
+                    .map(|(name, value)| member(name, value))
+                    .collect::<Result<_, _>>()?,
+            }),
+            (property_type, Value::String(value)) => PropertyValue::parse(property_type, value)?,
+            _ => return Err(invalid()),
+        };
+
+        Ok(Property {
+            name: raw.name,
+            value,
+        })
+    }
+}
+
+/// A member of a class value, JSON maps don't save their type so it's guessed from the value
+fn member(name: &str, value: &Value) -> Result<Property, String> {
+    let value = match value {
+        Value::Bool(value) => PropertyValue::Bool(*value),
+        Value::Number(number) => match number.as_i64() {
+            Some(value) => PropertyValue::Int(value),
+            None => PropertyValue::Float(number.as_f64().unwrap_or_default()),
+        },
+        Value::String(value) => PropertyValue::String(value.clone()),
+        Value::Object(members) => PropertyValue::Class(ClassValue {
+            property_type: String::new(),
+            members: members
+                .iter()
+                .map(|(name, value)| member(name, value))
+                .collect::<Result<_, _>>()?,
+        }),
+        _ => return Err(format!("invalid class member {}", name)),
+    };
+
+    Ok(Property {
+        name: name.to_string(),
+        value,
+    })
+}
+
+impl From<Property> for RawProperty {
+    fn from(property: Property) -> Self {
+        let property_type = property.value.type_name().to_string();
+        let (class, value) = match property.value {
+            PropertyValue::Class(class) => (class.property_type, class_json(class.members)),
+            value => (String::new(), scalar_json(value)),
+        };
+
+        RawProperty {
+            name: property.name,
+            property_type,
+            class,
+            value,
+        }
+    }
+}
+
+fn class_json(members: Vec<Property>) -> Value {
+    Value::Object(
+        members
+            .into_iter()
+            .map(|member| {
+                let value = match member.value {
+                    PropertyValue::Class(class) => class_json(class.members),
+                    value => scalar_json(value),
+                };
+                (member.name, value)
+            })
+            .collect(),
+    )
+}
+
+fn scalar_json(value: PropertyValue) -> Value {
+    match value {
+        PropertyValue::Bool(value) => Value::from(value),
+        PropertyValue::Int(value) => Value::from(value),
+        PropertyValue::Float(value) => Value::from(value),
+        PropertyValue::String(value) | PropertyValue::File(value) => Value::from(value),
+        PropertyValue::Color(color) => Value::from(format_color(color)),
+        PropertyValue::Object(value) => Value::from(value),
+        PropertyValue::Class(class) => class_json(class.members),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiled::Map;
+
+    #[test]
+    fn test_typed_properties() {
+        let map = Map::from_file("assets/test_maps/map.tmx").expect("Failed to load map");
+
+        let ambient = map.property_color("ambient").expect("Missing color");
+        assert_eq!(Color::rgba_u8(255, 128, 0, 128), ambient);
+        assert_eq!(Some("../music/theme.ogg"), map.property_file("music"));
+        assert_eq!(Some(2), map.property_object("target"));
+        assert_eq!(None, map.property_i32("target"));
+
+        let spawn = map.property_class("spawn").expect("Missing class");
+        assert_eq!("Spawn", spawn.property_type);
+        assert_eq!(Some(3), spawn.property_i32("count"));
+        assert_eq!(Some(0.5), spawn.property_f32("delay"));
+
+        let water = map.tilesets[0].tile(5).expect("Missing tile");
+        assert_eq!(Some(1.5), water.property_f32("cost"));
+        assert_eq!(Some("water"), water.property_str("kind"));
+        assert_eq!(None, water.property_bool("kind"));
+
+        // Floats that happen to be whole are still floats
+        let data = r#"[
+            {"name": "speed", "type": "float", "value": 2},
+            {"name": "tint", "type": "color", "value": ""},
+            {"name": "note", "value": "untyped"}
+        ]"#;
+        let properties: Vec<Property> = serde_json::from_str(data).expect("Failed to parse");
+        assert_eq!(PropertyValue::Float(2.0), properties[0].value);
+        assert_eq!(
+            PropertyValue::Color(Color::rgba_u8(0, 0, 0, 0)),
+            properties[1].value
+        );
+        assert_eq!(
+            PropertyValue::String("untyped".to_string()),
+            properties[2].value
+        );
+
+        let round_trip = serde_json::to_string(&map.properties).expect("Failed to serialize");
+        let parsed: Vec<Property> = serde_json::from_str(&round_trip).expect("Failed to parse");
+        assert_eq!(map.properties, parsed);
+
+        let invalid = r#"[{"name": "speed", "type": "int", "value": "fast"}]"#;
+        assert!(serde_json::from_str::<Vec<Property>>(invalid).is_err());
+    }
+}
@@ -0,0 +1,380 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use roxmltree::{Document, Node};
+
+use super::encoding::{decode_base64, Compression};
+use super::loader::MapLoader;
+use super::{
+    Bounds, Chunk, ClassValue, Frame, GroupLayer, ImageLayer, Layer, LayerKind, Map, MapError,
+    Object, ObjectGroup, Property, PropertyValue, Shape, Tile, TileLayer, TileRef, TileSet, Vertex,
+};
+
+/// Reads a `.tmx` map, its external tilesets are looked up relative to `directory`
+pub fn parse_map(data: &str, directory: &Path, loader: &mut MapLoader) -> Result<Map, MapError> {
+    let document = Document::parse(data)?;
+    let root = document.root_element();
+    if !root.has_tag_name("map") {
+        return Err(MapError::Xml(format!(
+            "expected <map>, found <{}>",
+            tag(root)
+        )));
+    }
+
+    Ok(Map {
+        height: attribute(root, "height")?,
+        width: attribute(root, "width")?,
+        layers: parse_layers(root)?,
+        tilesets: elements(root, "tileset")
+            .map(|node| parse_tileset_reference(node, directory, loader))
+            .collect::<Result<_, _>>()?,
+        tile_height: attribute(root, "tileheight")?,
+        tile_width: attribute(root, "tilewidth")?,
+        properties: parse_properties(root)?,
+    })
+}
+
+fn parse_tileset_reference(
+    node: Node,
+    directory: &Path,
+    loader: &mut MapLoader,
+) -> Result<TileSet, MapError> {
+    let first_gid = attribute(node, "firstgid")?;
+
+    match node.attribute("source") {
+        Some(source) => loader.external_tileset(directory, source, first_gid),
+        None => parse_tileset(node, first_gid),
+    }
+}
+
+/// Reads a `.tsx` tileset
+pub fn parse_tileset_file(data: &str) -> Result<TileSet, MapError> {
+    let document = Document::parse(data)?;
+    parse_tileset(document.root_element(), 0)
+}
+
+fn parse_tileset(node: Node, first_gid: i32) -> Result<TileSet, MapError> {
+    let image = required_child(node, "image")?;
+
+    Ok(TileSet {
+        first_gid,
+        columns: attribute(node, "columns")?,
+        image: attribute(image, "source")?,
+        image_height: attribute(image, "height")?,
+        image_width: attribute(image, "width")?,
+        name: attribute(node, "name")?,
+        tile_count: attribute(node, "tilecount")?,
+        tile_height: attribute(node, "tileheight")?,
+        tile_width: attribute(node, "tilewidth")?,
+        tiles: elements(node, "tile")
+            .map(|tile| {
+                Ok(Tile {
+                    id: attribute(tile, "id")?,
+                    properties: parse_properties(tile)?,
+                    animation: parse_animation(tile)?,
+                })
+            })
+            .collect::<Result<_, MapError>>()?,
+        properties: parse_properties(node)?,
+    })
+}
+
+fn parse_animation(tile: Node) -> Result<Vec<Frame>, MapError> {
+    let animation = match child(tile, "animation") {
+        Some(animation) => animation,
+        None => return Ok(vec![]),
+    };
+
+    elements(animation, "frame")
+        .map(|frame| {
+            Ok(Frame {
+                tile_id: attribute(frame, "tileid")?,
+                duration: attribute(frame, "duration")?,
+            })
+        })
+        .collect()
+}
+
+/// Layers in document order, the other children of `parent` are skipped
+fn parse_layers(parent: Node) -> Result<Vec<Layer>, MapError> {
+    parent
+        .children()
+        .filter_map(|node| parse_layer(node).transpose())
+        .collect()
+}
+
+/// None for the nodes that aren't layers
+fn parse_layer(node: Node) -> Result<Option<Layer>, MapError> {
+    let kind = match tag(node) {
+        "layer" => LayerKind::TileLayer(parse_tile_layer(node)?),
+        "objectgroup" => LayerKind::ObjectGroup(ObjectGroup {
+            objects: elements(node, "object")
+                .map(parse_object)
+                .collect::<Result<_, _>>()?,
+        }),
+        "imagelayer" => LayerKind::ImageLayer(ImageLayer {
+            image: match child(node, "image") {
+                Some(image) => attribute(image, "source")?,
+                None => String::new(),
+            },
+        }),
+        "group" => LayerKind::Group(GroupLayer {
+            layers: parse_layers(node)?,
+        }),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Layer {
+        id: attribute(node, "id")?,
+        name: attribute_or(node, "name", String::new())?,
+        visible: attribute_or(node, "visible", 1)? != 0,
+        opacity: attribute_or(node, "opacity", 1.0)?,
+        offset_x: attribute_or(node, "offsetx", 0.0)?,
+        offset_y: attribute_or(node, "offsety", 0.0)?,
+        properties: parse_properties(node)?,
+        kind,
+    }))
+}
+
+fn parse_tile_layer(node: Node) -> Result<TileLayer, MapError> {
+    let data = required_child(node, "data")?;
+
+    // Infinite maps split their layers in chunks
+    let chunks = if child(data, "chunk").is_some() {
+        elements(data, "chunk")
+            .map(|chunk| {
+                let bounds = Bounds {
+                    x: attribute(chunk, "x")?,
+                    y: attribute(chunk, "y")?,
+                    width: attribute(chunk, "width")?,
+                    height: attribute(chunk, "height")?,
+                };
+                Chunk::new(bounds, parse_tiles(data, chunk)?).map_err(MapError::Xml)
+            })
+            .collect::<Result<_, _>>()?
+    } else {
+        let bounds = Bounds {
+            x: 0,
+            y: 0,
+            width: attribute(node, "width")?,
+            height: attribute(node, "height")?,
+        };
+        vec![Chunk::new(bounds, parse_tiles(data, data)?).map_err(MapError::Xml)?]
+    };
+
+    Ok(TileLayer::new(chunks))
+}
+
+/// Tiles of `node`, encoded as the `data` element says
+fn parse_tiles(data: Node, node: Node) -> Result<Vec<TileRef>, MapError> {
+    let text = node.text().unwrap_or("").trim();
+
+    match data.attribute("encoding") {
+        Some("csv") => text
+            .split(',')
+            .map(|gid| Ok(TileRef::from(parse::<u32>(gid.trim())?)))
+            .collect(),
+        Some("base64") => {
+            let compression = match data.attribute("compression") {
+                None | Some("") => Compression::None,
+                Some("zlib") => Compression::Zlib,
+                Some("gzip") => Compression::Gzip,
+                Some("zstd") => Compression::Zstd,
+                Some(compression) => {
+                    return Err(MapError::Xml(format!(
+                        "unknown compression {}",
+                        compression
+                    )))
+                }
+            };
+            decode_base64(text, compression).map_err(MapError::Xml)
+        }
+        // One <tile> element per cell
+        None => elements(node, "tile")
+            .map(|tile| Ok(TileRef::from(attribute_or::<u32>(tile, "gid", 0)?)))
+            .collect(),
+        Some(encoding) => Err(MapError::Xml(format!("unknown encoding {}", encoding))),
+    }
+}
+
+fn parse_object(node: Node) -> Result<Object, MapError> {
+    let width = attribute_or(node, "width", 0.0)?;
+    let height = attribute_or(node, "height", 0.0)?;
+
+    let shape = if child(node, "point").is_some() {
+        Shape::Point
+    } else if child(node, "ellipse").is_some() {
+        Shape::Ellipse { width, height }
+    } else if let Some(polygon) = child(node, "polygon") {
+        Shape::Polygon(parse_points(polygon)?)
+    } else if let Some(polyline) = child(node, "polyline") {
+        Shape::Polyline(parse_points(polyline)?)
+    } else {
+        Shape::Rectangle { width, height }
+    };
+
+    Ok(Object {
+        id: attribute(node, "id")?,
+        name: attribute_or(node, "name", String::new())?,
+        // Called class since Tiled 1.9
+        object_type: node
+            .attribute("type")
+            .or_else(|| node.attribute("class"))
+            .unwrap_or("")
+            .to_string(),
+        x: attribute(node, "x")?,
+        y: attribute(node, "y")?,
+        rotation: attribute_or(node, "rotation", 0.0)?,
+        gid: node
+            .attribute("gid")
+            .map(|gid| parse::<u32>(gid).map(TileRef::from))
+            .transpose()?,
+        visible: attribute_or(node, "visible", 1)? != 0,
+        shape,
+        properties: parse_properties(node)?,
+    })
+}
+
+/// `points` is a space separated list of `x,y` pairs
+fn parse_points(node: Node) -> Result<Vec<Vertex>, MapError> {
+    let points: &str = node.attribute("points").unwrap_or("");
+
+    points
+        .split_whitespace()
+        .map(|point| {
+            let mut coordinates = point.splitn(2, ',');
+            Ok(Vertex {
+                x: parse(coordinates.next().unwrap_or(""))?,
+                y: parse(coordinates.next().unwrap_or(""))?,
+            })
+        })
+        .collect()
+}
+
+fn parse_properties(node: Node) -> Result<Vec<Property>, MapError> {
+    match child(node, "properties") {
+        Some(properties) => elements(properties, "property")
+            .map(parse_property)
+            .collect(),
+        None => Ok(vec![]),
+    }
+}
+
+fn parse_property(property: Node) -> Result<Property, MapError> {
+    let property_type = property.attribute("type").unwrap_or("string");
+
+    let value = if property_type == "class" {
+        // Only the members that were set are saved, in their own properties
+        let mut members = parse_properties(property)?;
+        members.sort_by(|a, b| a.name.cmp(&b.name));
+
+        PropertyValue::Class(ClassValue {
+            property_type: attribute_or(property, "propertytype", String::new())?,
+            members,
+        })
+    } else {
+        // Multiline strings are the text of the element
+        let value = property
+            .attribute("value")
+            .or_else(|| property.text())
+            .unwrap_or("");
+        PropertyValue::parse(property_type, value).map_err(MapError::Xml)?
+    };
+
+    Ok(Property {
+        name: attribute(property, "name")?,
+        value,
+    })
+}
+
+fn tag<'a>(node: Node<'a, '_>) -> &'a str {
+    node.tag_name().name()
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn required_child<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &str,
+) -> Result<Node<'a, 'input>, MapError> {
+    child(node, name).ok_or_else(|| MapError::Xml(format!("<{}> has no <{}>", tag(node), name)))
+}
+
+fn elements<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.has_tag_name(name))
+}
+
+fn attribute<T: FromStr>(node: Node, name: &str) -> Result<T, MapError> {
+    match node.attribute(name) {
+        Some(value) => parse(value),
+        None => Err(MapError::Xml(format!("<{}> has no {}", tag(node), name))),
+    }
+}
+
+fn attribute_or<T: FromStr>(node: Node, name: &str, default: T) -> Result<T, MapError> {
+    node.attribute(name).map_or(Ok(default), parse)
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, MapError> {
+    value
+        .parse()
+        .map_err(|_| MapError::Xml(format!("invalid value {:?}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tmx_matches_json_export() {
+        let tmx = Map::from_file("assets/test_maps/map.tmx").expect("Failed to load tmx map");
+        let json = Map::from_file("assets/test_maps/map.json").expect("Failed to load json map");
+        assert_eq!(json, tmx);
+
+        assert_eq!("../tileset.png", tmx.tilesets[0].image);
+        assert_eq!(5, tmx.all_layers().len());
+        assert!(tmx.tile_layers()[0].1.chunks[0].data[3].flip_h);
+
+        let water = tmx.tilesets[0].tile(5).expect("Missing tile");
+        let frames: Vec<(i32, u32)> = water
+            .animation
+            .iter()
+            .map(|frame| (frame.tile_id, frame.duration))
+            .collect();
+        assert_eq!(vec![(5, 200), (6, 300)], frames);
+
+        let missing = "assets/test_maps/missing.tmx";
+        assert!(matches!(Map::from_file(missing), Err(MapError::Io(_))));
+        let invalid = parse_map(
+            "<map width=\"4\"/>",
+            Path::new("assets/test_maps"),
+            &mut MapLoader::default(),
+        );
+        assert!(matches!(invalid, Err(MapError::Xml(_))));
+    }
+
+    #[test]
+    fn test_infinite_tmx_matches_json_export() {
+        let tmx = Map::from_file("assets/test_maps/infinite.tmx").expect("Failed to load map");
+        let json = Map::from_file("assets/test_maps/infinite.json").expect("Failed to load map");
+        assert_eq!(json, tmx);
+    }
+
+    #[test]
+    fn test_tile_object_flips() {
+        let document = Document::parse(
+            r#"<object id="1" x="0" y="32" width="32" height="32" gid="1073741829"/>"#,
+        )
+        .expect("Failed to parse xml");
+        let object = parse_object(document.root_element()).expect("Failed to parse object");
+
+        let tile = object.gid.expect("Missing gid");
+        assert_eq!((5, false, true), (tile.gid, tile.flip_h, tile.flip_v));
+    }
+}
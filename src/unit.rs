@@ -1,89 +1,372 @@
-use crate::animation::{Animation, Animations};
+use crate::animation::Animations;
+use crate::archetype::{ArchetypesReloaded, UnitArchetype, UnitArchetypes};
+use crate::combat::{Armor, AttackOrder, Dying, Health};
+use crate::math::{Scalar, Vector};
+use crate::player::{Owner, PlayerId, Players};
+use crate::projectile::Projectile;
+use crate::simulation::MapLoaded;
+use crate::tiled::{CustomProperties, Map, PropertyValue};
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
+/// Objects of this type in the map's object groups are units
+pub const UNIT_OBJECT_TYPE: &str = "unit";
+
+/// Spawns the units placed on the map, or once it's loaded if the asset server loads it
+/// Expects the `Map` and the `UnitArchetypes` as resources.
 pub struct UnitPlugin;
 
 impl Plugin for UnitPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_system(setup.system());
+        if !app.resources().contains::<Events<MapLoaded>>() {
+            app.add_event::<MapLoaded>();
+        }
+
+        app.init_resource::<Players>()
+            .add_startup_system(setup.system())
+            .add_system(map_loaded_system.system());
+    }
+}
+
+fn setup(
+    commands: &mut Commands,
+    archetypes: Res<UnitArchetypes>,
+    players: Res<Players>,
+    map: Res<Map>,
+) {
+    spawn_initial_units(commands, &archetypes, &players, &map);
+}
+
+fn map_loaded_system(
+    commands: &mut Commands,
+    mut reader: EventReader<MapLoaded>,
+    archetypes: Res<UnitArchetypes>,
+    players: Res<Players>,
+    map: Res<Map>,
+) {
+    if reader.iter().next().is_some() {
+        spawn_initial_units(commands, &archetypes, &players, &map);
+    }
+}
+
+/// Gives units the sprite and animations of their archetype,
+/// and keeps the sprite in sync with the simulation
+pub struct UnitSpritePlugin;
+
+impl Plugin for UnitSpritePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // Sent when archetypes are reloaded
+        if !app.resources().contains::<Events<ArchetypesReloaded>>() {
+            app.add_event::<ArchetypesReloaded>();
+        }
+
+        app.init_resource::<UnitSpriteSheets>()
+            .add_system(spawn_sprite_system.system())
+            .add_system(reload_sprite_system.system())
+            .add_system(transform_system.system())
+            .add_system(unit_color_system.system())
+            .add_system(facing_system.system())
+            .add_system(animation_system.system())
+            .add_system(death_animation_system.system());
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Unit {
+    /// Id of the `UnitArchetype` it was spawned from
+    pub archetype: String,
     pub selected: bool,
-    pub velocity: Vec2,
-    pub max_speed: f32,
-    pub max_force: f32,
+    pub velocity: Vector,
+    pub max_speed: Scalar,
+    pub max_force: Scalar,
+    /// Width and height it collides with
+    pub size: Vector,
+    pub facing: Facing,
+}
+
+/// Sprites face right, they are flipped when facing left
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Facing {
+    Left,
+    Right,
 }
 
-fn spawn_unit(
+impl Facing {
+    fn sign(self) -> f32 {
+        match self {
+            Facing::Left => -1.0,
+            Facing::Right => 1.0,
+        }
+    }
+}
+
+/// Where the unit is in the simulation, its `Transform` follows it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Position(pub Vector);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MoveOrder {
+    pub path: Vec<Vector>,
+}
+
+/// Units with an `Attack` on their way to `target` stop to fight the enemies
+/// coming in acquisition range, then carry on
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttackMoveOrder {
+    pub target: Option<Vector>,
+}
+
+/// Texture atlas of each archetype, loaded when the first unit of that archetype shows up
+#[derive(Default)]
+struct UnitSpriteSheets(HashMap<String, Handle<TextureAtlas>>);
+
+impl UnitSpriteSheets {
+    fn get_or_load(
+        &mut self,
+        id: &str,
+        archetype: &UnitArchetype,
+        asset_server: &AssetServer,
+        texture_atlases: &mut Assets<TextureAtlas>,
+    ) -> Handle<TextureAtlas> {
+        self.0
+            .entry(id.to_string())
+            .or_insert_with(|| {
+                let sprite = &archetype.sprite;
+                let texture_handle = asset_server.load(sprite.texture.as_str());
+                let texture_atlas = TextureAtlas::from_grid(
+                    texture_handle,
+                    Vec2::new(sprite.tile_size.0, sprite.tile_size.1),
+                    sprite.columns,
+                    sprite.rows,
+                );
+                texture_atlases.add(texture_atlas)
+            })
+            .clone()
+    }
+}
+
+/// Spawns a unit of the `archetype` defined in `UnitArchetypes`
+pub fn spawn_unit(
     commands: &mut Commands,
-    translation: Vec3,
-    texture_atlas_handle: Handle<TextureAtlas>,
+    archetypes: &UnitArchetypes,
+    archetype: &str,
+    owner: PlayerId,
+    position: Vec2,
+    facing: Facing,
 ) {
-    let mut animations = HashMap::<String, Animation>::new();
-
-    animations.insert("idle".to_string(), Animation::new(vec![1, 2, 3, 4]));
-    animations.insert("moving".to_string(), Animation::new(vec![4, 5, 6, 7, 8, 9]));
-
-    commands
-        .spawn(SpriteSheetBundle {
-            texture_atlas: texture_atlas_handle,
-            transform: Transform {
-                translation,
-                scale: Vec3::new(1.25, 1.25, 999.0),
-                ..Default::default()
-            },
-            sprite: TextureAtlasSprite {
-                index: 0,
-                color: Color::WHITE,
+    let definition = match archetypes.get(archetype) {
+        Some(definition) => definition,
+        None => {
+            warn!("Not spawning unknown unit archetype {}", archetype);
+            return;
+        }
+    };
+
+    commands.spawn((
+        Owner(owner),
+        MoveOrder { path: vec![] },
+        Position(Vector::from(position)),
+        Unit {
+            facing,
+            ..definition.unit(archetype)
+        },
+        Health::new(definition.health),
+        Armor(definition.armor),
+    ));
+
+    if let Some(attack) = definition.attack() {
+        commands
+            .with(attack)
+            .with(AttackOrder::default())
+            .with(AttackMoveOrder::default());
+    }
+}
+
+/// Spawns the units every game starts with, the map objects of type `UNIT_OBJECT_TYPE`
+/// Their `archetype` and `owner` come from the object's custom properties,
+/// along with an optional `facing`, "left" or "right".
+pub fn spawn_initial_units(
+    commands: &mut Commands,
+    archetypes: &UnitArchetypes,
+    players: &Players,
+    map: &Map,
+) {
+    let projection = map.projection();
+
+    for object in map
+        .objects()
+        .into_iter()
+        .filter(|object| object.object_type == UNIT_OBJECT_TYPE)
+    {
+        let archetype = match object.property_str("archetype") {
+            Some(archetype) => archetype,
+            _ => {
+                warn!("Unit object {} has no archetype", object.id);
+                continue;
+            }
+        };
+
+        let owner = match object.property("owner") {
+            Some(PropertyValue::Int(owner)) => match u8::try_from(*owner).map(PlayerId) {
+                Ok(player) if players.get(player).is_some() => player,
+                _ => {
+                    warn!("Unit object {} has an unknown owner {}", object.id, owner);
+                    continue;
+                }
             },
-            ..Default::default()
-        })
-        .with(Timer::from_seconds(0.1, true))
-        .with(Animations::new("idle".to_string(), animations))
-        .with(MoveOrder { path: vec![] })
-        .with(Unit {
-            selected: false,
-            velocity: Vec2::zero(),
-            max_speed: 100.0,
-            max_force: 250.0,
-        });
+            _ => {
+                warn!("Unit object {} has no owner", object.id);
+                continue;
+            }
+        };
+
+        let facing = match object.property_str("facing") {
+            Some("left") => Facing::Left,
+            _ => Facing::Right,
+        };
+
+        let (x, y) = object.center();
+        let position = projection.world_position(x, y);
+
+        spawn_unit(commands, archetypes, archetype, owner, position, facing);
+    }
 }
 
-fn setup(
+fn spawn_sprite_system(
     commands: &mut Commands,
     asset_server: Res<AssetServer>,
+    archetypes: Res<UnitArchetypes>,
+    players: Res<Players>,
+    mut sprite_sheets: ResMut<UnitSpriteSheets>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    query: Query<(Entity, &Position, &Owner, &Unit, Option<&Animations>), Added<Unit>>,
 ) {
-    let texture_handle = asset_server.load("dino.png");
-    let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(24.0, 24.0), 24, 1);
-    let texture_atlas_handle = texture_atlases.add(texture_atlas);
-
-    spawn_unit(
-        commands,
-        Vec3::new(100.0, 100.0, 500.0),
-        texture_atlas_handle.clone(),
-    );
-    spawn_unit(
-        commands,
-        Vec3::new(0.0, 0.0, 500.0),
-        texture_atlas_handle.clone(),
-    );
-    spawn_unit(
-        commands,
-        Vec3::new(-100.0, -100.0, 500.0),
-        texture_atlas_handle.clone(),
-    );
-    spawn_unit(
-        commands,
-        Vec3::new(23.0, 42.0, 500.0),
-        texture_atlas_handle.clone(),
-    );
+    for (entity, position, owner, unit, saved_animations) in query.iter() {
+        let archetype = match archetypes.get(&unit.archetype) {
+            Some(archetype) => archetype,
+            None => {
+                warn!("No sprite for unknown unit archetype {}", unit.archetype);
+                continue;
+            }
+        };
+
+        // Units restored from a save come with their animation state
+        if saved_animations.is_none() {
+            commands.insert_one(entity, archetype.animations());
+        }
+
+        let texture_atlas = sprite_sheets.get_or_load(
+            &unit.archetype,
+            archetype,
+            &asset_server,
+            &mut texture_atlases,
+        );
+        let scale = archetype.sprite.scale;
+        let scale_x = scale * unit.facing.sign();
+
+        commands
+            .insert(
+                entity,
+                SpriteSheetBundle {
+                    texture_atlas,
+                    transform: Transform {
+                        translation: position.0.to_vec2().extend(500.0),
+                        scale: Vec3::new(scale_x, scale, 999.0),
+                        ..Default::default()
+                    },
+                    sprite: TextureAtlasSprite {
+                        index: 0,
+                        color: players.unit_color(owner.0, unit.selected),
+                    },
+                    ..Default::default()
+                },
+            )
+            .insert_one(entity, Timer::from_seconds(0.1, true));
+    }
 }
 
-pub struct MoveOrder {
-    pub path: Vec<Vec2>,
+/// Swaps the sprite sheet, scale and animations of units whose archetype was reloaded
+fn reload_sprite_system(
+    mut reader: EventReader<ArchetypesReloaded>,
+    asset_server: Res<AssetServer>,
+    archetypes: Res<UnitArchetypes>,
+    mut sprite_sheets: ResMut<UnitSpriteSheets>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut query: Query<(
+        &Unit,
+        &mut Handle<TextureAtlas>,
+        &mut Transform,
+        &mut Animations,
+    )>,
+) {
+    for ArchetypesReloaded(ids) in reader.iter() {
+        for id in ids.iter() {
+            sprite_sheets.0.remove(id);
+        }
+
+        for (unit, mut texture_atlas, mut transform, mut animations) in query.iter_mut() {
+            let archetype = match archetypes.get(&unit.archetype) {
+                Some(archetype) if ids.contains(&unit.archetype) => archetype,
+                _ => continue,
+            };
+
+            *texture_atlas = sprite_sheets.get_or_load(
+                &unit.archetype,
+                archetype,
+                &asset_server,
+                &mut texture_atlases,
+            );
+            transform.scale.x = archetype.sprite.scale * unit.facing.sign();
+            transform.scale.y = archetype.sprite.scale;
+
+            let current_animation = animations.current_animation.clone();
+            *animations = archetype.animations();
+            animations.play(current_animation);
+        }
+    }
+}
+
+/// Projectiles have their own, they're drawn above the ground
+fn transform_system(
+    mut query: Query<(&Position, &mut Transform), (Changed<Position>, Without<Projectile>)>,
+) {
+    for (position, mut transform) in query.iter_mut() {
+        let position = position.0.to_vec2();
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+}
+
+fn unit_color_system(
+    players: Res<Players>,
+    mut query: Query<(&Owner, &Unit, &mut TextureAtlasSprite), Changed<Unit>>,
+) {
+    for (owner, unit, mut sprite) in query.iter_mut() {
+        sprite.color = players.unit_color(owner.0, unit.selected);
+    }
+}
+
+fn facing_system(mut query: Query<(&Unit, &mut Transform), Changed<Unit>>) {
+    for (unit, mut transform) in query.iter_mut() {
+        transform.scale.x = transform.scale.x.abs() * unit.facing.sign();
+    }
+}
+
+fn animation_system(mut query: Query<(&Unit, &mut Animations), Without<Dying>>) {
+    for (unit, mut animations) in query.iter_mut() {
+        if unit.velocity.to_vec2() > Vec2::zero() {
+            animations.play("idle".to_string());
+        } else {
+            animations.play("moving".to_string());
+        }
+    }
+}
+
+fn death_animation_system(mut query: Query<&mut Animations, Added<Dying>>) {
+    for mut animations in query.iter_mut() {
+        animations.play("dying".to_string());
+    }
 }